@@ -0,0 +1,53 @@
+#!/usr/bin/env bash
+# Runs windshot inside a headless sway instance and checks which overlay
+# mode it picked. Extra arguments are passed to windshot.
+# With WINDSHOT_NO_OVERLAY set, only windshot's exit status is checked.
+#
+# Requires sway and a built windshot binary (cargo build).
+set -euo pipefail
+
+BIN="${WINDSHOT_BIN:-target/debug/windshot}"
+TIMEOUT="${WINDSHOT_TIMEOUT:-5}"
+RUNTIME_DIR="$(mktemp -d)"
+LOG="$RUNTIME_DIR/windshot.log"
+trap 'kill "$SWAY_PID" 2>/dev/null || true; rm -rf "$RUNTIME_DIR"' EXIT
+
+export XDG_RUNTIME_DIR="$RUNTIME_DIR"
+export WLR_BACKENDS=headless
+export WLR_RENDERER=pixman
+export WLR_LIBINPUT_NO_DEVICES=1
+unset DISPLAY WAYLAND_DISPLAY
+
+sway -c /dev/null >"$RUNTIME_DIR/sway.log" 2>&1 &
+SWAY_PID=$!
+
+for _ in $(seq 50); do
+    SOCKET="$(find "$RUNTIME_DIR" -maxdepth 1 -name 'wayland-*' ! -name '*.lock' -print -quit)"
+    [ -n "$SOCKET" ] && break
+    sleep 0.1
+done
+if [ -z "${SOCKET:-}" ]; then
+    echo "sway did not start:" >&2
+    cat "$RUNTIME_DIR/sway.log" >&2
+    exit 1
+fi
+export WAYLAND_DISPLAY="$(basename "$SOCKET")"
+export GDK_BACKEND=wayland
+
+STATUS=0
+timeout "$TIMEOUT" "$BIN" "$@" >"$LOG" 2>&1 || STATUS=$?
+cat "$LOG"
+
+# For runs that never show the overlay, like with --no-edit.
+if [ -n "${WINDSHOT_NO_OVERLAY:-}" ]; then
+    exit "$STATUS"
+fi
+
+if grep -q "Presenting overlay as LayerShell" "$LOG"; then
+    echo "ok: overlay shown on the layer shell"
+elif grep -q "Presenting overlay as Fullscreen" "$LOG"; then
+    echo "ok: overlay shown as a fullscreen toplevel"
+else
+    echo "fail: overlay was never presented" >&2
+    exit 1
+fi
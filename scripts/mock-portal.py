@@ -0,0 +1,66 @@
+#!/usr/bin/env python3
+# Minimal stand-in for xdg-desktop-portal's Screenshot interface. Every
+# Screenshot call succeeds and answers with the image given on the command
+# line. Meant to be run on a private session bus, see portal.sh.
+import sys
+
+from gi.repository import Gio, GLib
+
+INTERFACE_XML = """
+<node>
+  <interface name="org.freedesktop.portal.Screenshot">
+    <method name="Screenshot">
+      <arg type="s" name="parent_window" direction="in"/>
+      <arg type="a{sv}" name="options" direction="in"/>
+      <arg type="o" name="handle" direction="out"/>
+    </method>
+    <property name="version" type="u" access="read"/>
+  </interface>
+</node>
+"""
+
+OBJECT_PATH = "/org/freedesktop/portal/desktop"
+IMAGE_URI = Gio.File.new_for_commandline_arg(sys.argv[1]).get_uri()
+
+
+def on_method_call(connection, sender, path, interface, method, parameters, invocation):
+    _parent_window, options = parameters.unpack()
+    token = options.get("handle_token", "windshot")
+    handle = "%s/request/%s/%s" % (OBJECT_PATH, sender[1:].replace(".", "_"), token)
+    invocation.return_value(GLib.Variant("(o)", (handle,)))
+
+    def respond():
+        results = {"uri": GLib.Variant("s", IMAGE_URI)}
+        connection.emit_signal(
+            sender,
+            handle,
+            "org.freedesktop.portal.Request",
+            "Response",
+            GLib.Variant("(ua{sv})", (0, results)),
+        )
+        print("mock-portal: answered %s with %s" % (sender, IMAGE_URI), flush=True)
+        return GLib.SOURCE_REMOVE
+
+    GLib.idle_add(respond)
+
+
+def on_get_property(connection, sender, path, interface, name):
+    if name == "version":
+        return GLib.Variant("u", 2)
+    return None
+
+
+def on_bus_acquired(connection, name):
+    interface = Gio.DBusNodeInfo.new_for_xml(INTERFACE_XML).interfaces[0]
+    connection.register_object(OBJECT_PATH, interface, on_method_call, on_get_property, None)
+
+
+Gio.bus_own_name(
+    Gio.BusType.SESSION,
+    "org.freedesktop.portal.Desktop",
+    Gio.BusNameOwnerFlags.NONE,
+    on_bus_acquired,
+    None,
+    None,
+)
+GLib.MainLoop().run()
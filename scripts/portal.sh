@@ -0,0 +1,30 @@
+#!/usr/bin/env bash
+# Runs windshot with the portal capture backend against mock-portal.py on a
+# private session bus, inside the headless compositor from headless.sh.
+#
+# Usage: portal.sh <image> [windshot arguments...]
+# Requires dbus-run-session, gdbus and PyGObject on top of headless.sh's needs.
+set -euo pipefail
+
+SCRIPTS="$(cd "$(dirname "$0")" && pwd)"
+IMAGE="${1:?usage: portal.sh <image> [windshot arguments...]}"
+shift
+
+if [ -z "${WINDSHOT_PRIVATE_BUS:-}" ]; then
+    exec env WINDSHOT_PRIVATE_BUS=1 dbus-run-session -- "$0" "$IMAGE" "$@"
+fi
+
+python3 "$SCRIPTS/mock-portal.py" "$IMAGE" &
+MOCK_PID=$!
+trap 'kill "$MOCK_PID" 2>/dev/null || true' EXIT
+gdbus wait --session --timeout 5 org.freedesktop.portal.Desktop
+
+OUTPUT="$(WINDSHOT_CAPTURE_BACKEND=portal "$SCRIPTS/headless.sh" "$@")"
+echo "$OUTPUT"
+
+if echo "$OUTPUT" | grep -q "with Portal"; then
+    echo "ok: captured through the portal"
+else
+    echo "fail: no capture came from the portal" >&2
+    exit 1
+fi
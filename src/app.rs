@@ -0,0 +1,309 @@
+//! What happens once the command line has been parsed: capturing, showing
+//! the editor and handing the result to wherever it was asked to go.
+
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+
+use gtk4::prelude::*;
+use gtk4::Application;
+
+use crate::capture::{self, CaptureBackend, CaptureBoxed, CaptureTarget, CapturedOutput};
+use crate::cli::{self, CliOptions, Mode};
+use crate::editing_area::{Command, EditingArea, MaybeSelection, MaybeSelectionBoxed, Selection};
+use crate::export::ExportError;
+use crate::overlay;
+use crate::render::RenderError;
+
+/// Log domain of debug messages, shown with `G_MESSAGES_DEBUG=windshot`.
+const LOG_DOMAIN: &str = "windshot";
+
+thread_local! {
+    /// Set when a local invocation fails after the main loop started, since
+    /// GApplication reports success for those no matter what.
+    static FAILED: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Whether a local invocation failed after its handler returned.
+pub fn failed() -> bool {
+    FAILED.with(Cell::get)
+}
+
+/// The command line of the invocation being served. Dropping it lets a
+/// remote invoker exit, so it is only taken once we are done.
+type Invocation = Rc<RefCell<Option<gio::ApplicationCommandLine>>>;
+
+/// Handler for [`gio::Application`]'s `command-line` signal.
+pub fn handle_command_line(
+    app: &Application,
+    command_line: &gio::ApplicationCommandLine,
+) -> glib::ExitCode {
+    let options = match cli::parse(command_line) {
+        Ok(options) => options,
+        Err(error) => {
+            command_line.printerr_literal(&format!("windshot: {}\n", error));
+            return glib::ExitCode::FAILURE;
+        }
+    };
+
+    let invocation: Invocation = Rc::new(RefCell::new(Some(command_line.clone())));
+    if options.delay > 0.0 {
+        let guard = app.hold();
+        let app = app.clone();
+        glib::timeout_add_local_once(
+            std::time::Duration::from_secs_f64(options.delay),
+            move || {
+                run(&app, options, invocation);
+                drop(guard);
+            },
+        );
+    } else {
+        run(app, options, invocation);
+    }
+    glib::ExitCode::SUCCESS
+}
+
+fn run(app: &Application, options: CliOptions, invocation: Invocation) {
+    let editing_area = EditingArea::new();
+
+    let monitor = match find_monitor(&options.mode) {
+        Ok(monitor) => monitor,
+        Err(error) => return fail(&invocation, &error),
+    };
+
+    // Capture before the overlay is mapped so it does not end up in the screenshot.
+    let capture = match capture_for(&options.mode) {
+        Ok(capture) => capture,
+        Err(error) => return fail(&invocation, &error),
+    };
+
+    // Where the widget sits in the compositor's space and what starts out
+    // selected, in widget coordinates.
+    let (origin, initial) = match &options.mode {
+        Mode::Region => (monitor_origin(monitor.as_ref()), None),
+        Mode::Output(_) => {
+            let geometry = monitor.as_ref().map(|monitor| monitor.geometry());
+            let size = geometry.map(|geometry| (geometry.width(), geometry.height()));
+            (monitor_origin(monitor.as_ref()), size.map(|size| ((0, 0), size)))
+        }
+        Mode::Full => {
+            let origin = monitor_origin(monitor.as_ref());
+            let initial = capture
+                .bounds()
+                .map(|(x, y, width, height)| ((x - origin.0, y - origin.1), (width, height)));
+            (origin, initial)
+        }
+        // The window or image is drawn at the widget's origin.
+        Mode::Window(_) | Mode::Edit(_) => (
+            (0, 0),
+            capture.first().map(|image| ((0, 0), (image.width, image.height))),
+        ),
+    };
+
+    let initial = match options.geometry {
+        // The geometry is global, the selection is relative to the widget.
+        Some(mut geometry) => {
+            geometry.move_relative(-origin.0 as f32, -origin.1 as f32);
+            Some(geometry)
+        }
+        None => initial.map(|((x, y), (width, height))| {
+            Selection::new_from_size((x as f32, y as f32), width as f32, height as f32)
+        }),
+    };
+
+    // Before the initial selection, which is taken as given.
+    editing_area.set_constraint(options.constraint);
+    editing_area.set_export_scale(options.scale);
+    editing_area.set_origin_x(origin.0);
+    editing_area.set_origin_y(origin.1);
+    editing_area.set_capture(capture.clone());
+    // Windows are only where we think they are on a live capture of the screen.
+    let live = matches!(options.mode, Mode::Region | Mode::Output(_) | Mode::Full);
+    let toplevels = if live { capture::visible_windows() } else { Vec::new() };
+    editing_area.set_windows(toplevels.clone());
+    if let Some(selection) = initial {
+        editing_area.set_selection(MaybeSelectionBoxed::from(MaybeSelection::Selection(selection)));
+    }
+    for region in &options.redact {
+        let region = region.translated(-origin.0 as f32, -origin.1 as f32);
+        editing_area.push_command(Command::redact(&region));
+    }
+
+    let editing_area_clone = editing_area.clone();
+    app.connect_shutdown(move |_| editing_area_clone.persist_clipboard());
+
+    if options.no_edit {
+        finish(&editing_area, &options, &invocation);
+        return;
+    }
+
+    // One overlay per output when selecting from the whole desktop, all of
+    // them editing the same selection and commands.
+    let mut overlays = vec![(monitor.clone(), editing_area.clone())];
+    if matches!(options.mode, Mode::Region | Mode::Full) {
+        for other in all_monitors().into_iter().filter(|other| Some(other) != monitor.as_ref()) {
+            let geometry = other.geometry();
+            let other_area = EditingArea::new();
+            other_area.set_origin_x(geometry.x());
+            other_area.set_origin_y(geometry.y());
+            other_area.set_capture(capture.clone());
+            other_area.set_windows(toplevels.clone());
+            editing_area.share_with(&other_area);
+            let other_area_clone = other_area.clone();
+            app.connect_shutdown(move |_| other_area_clone.persist_clipboard());
+            overlays.push((Some(other), other_area));
+        }
+    }
+
+    let windows: Rc<Vec<gtk4::ApplicationWindow>> = Rc::new(
+        overlays
+            .iter()
+            .map(|(monitor, editing_area)| {
+                let (window, mode) = overlay::build_overlay_window(app, monitor.as_ref(), editing_area);
+                glib::g_debug!(LOG_DOMAIN, "Presenting overlay as {:?}", mode);
+                window
+            })
+            .collect(),
+    );
+    let close_all = {
+        let windows = windows.clone();
+        move || windows.iter().for_each(|window| window.close())
+    };
+
+    let options = Rc::new(options);
+    for (_, editing_area) in &overlays {
+        let options = options.clone();
+        let invocation_clone = invocation.clone();
+        let close_all_clone = close_all.clone();
+        editing_area.connect_confirmed(move |editing_area| {
+            finish(editing_area, &options, &invocation_clone);
+            close_all_clone();
+        });
+        let invocation_clone = invocation.clone();
+        let close_all_clone = close_all.clone();
+        editing_area.connect_cancelled(move |_| {
+            fail(&invocation_clone, "cancelled");
+            close_all_clone();
+        });
+    }
+
+    for window in windows.iter() {
+        window.set_visible(true);
+    }
+}
+
+fn all_monitors() -> Vec<gdk4::Monitor> {
+    gdk4::Display::default()
+        .map(|display| display.monitors().iter().flatten().collect())
+        .unwrap_or_default()
+}
+
+/// The monitor the main overlay goes on: the one asked for, or the first one.
+fn find_monitor(mode: &Mode) -> Result<Option<gdk4::Monitor>, String> {
+    let monitors = all_monitors();
+
+    match mode {
+        Mode::Output(name) => monitors
+            .into_iter()
+            .find(|monitor| monitor.connector().as_deref() == Some(name.as_str()))
+            .map(Some)
+            .ok_or_else(|| format!("no output named {:?}", name)),
+        _ => Ok(monitors.into_iter().next()),
+    }
+}
+
+fn monitor_origin(monitor: Option<&gdk4::Monitor>) -> (i32, i32) {
+    monitor
+        .map(|monitor| (monitor.geometry().x(), monitor.geometry().y()))
+        .unwrap_or_default()
+}
+
+fn capture_for(mode: &Mode) -> Result<CaptureBoxed, String> {
+    if let Mode::Edit(path) = mode {
+        let texture = gdk4::Texture::from_filename(path)
+            .map_err(|error| format!("could not open {}: {}", path.display(), error))?;
+        return Ok(CaptureBoxed::from(vec![CapturedOutput {
+            name: None,
+            x: 0,
+            y: 0,
+            width: texture.width(),
+            height: texture.height(),
+            texture,
+        }]));
+    }
+
+    let target = match mode {
+        Mode::Window(query) => CaptureTarget::Window(query.clone()),
+        _ => CaptureTarget::AllOutputs,
+    };
+    let backend = CaptureBackend::detect();
+    let capture = backend
+        .capture(&target)
+        .map_err(|error| format!("could not capture with {:?}: {}", backend, error))?;
+    glib::g_debug!(LOG_DOMAIN, "Captured {} output(s) with {:?}", capture.len(), backend);
+    Ok(capture)
+}
+
+/// Sends the result where the options say: a file, the clipboard, stdout
+/// for the geometry, or the pictures directory when none was given.
+fn finish(editing_area: &EditingArea, options: &CliOptions, invocation: &Invocation) {
+    let mut geometry = None;
+    let mut messages = Vec::new();
+    let mut result: Result<(), ExportError> = Ok(());
+
+    if options.print_geometry {
+        match editing_area.selection().0 {
+            MaybeSelection::Selection(mut selection) => {
+                selection.move_relative(editing_area.origin_x() as f32, editing_area.origin_y() as f32);
+                geometry = Some(selection.to_string());
+            }
+            MaybeSelection::NoSelection(_) => result = Err(RenderError::EmptySelection.into()),
+        }
+    }
+
+    if let Some(path) = options.output.as_ref().filter(|_| result.is_ok()) {
+        match editing_area.export_to_file(path, &options.export) {
+            Ok(()) => messages.push(format!("Saved to {}", path.display())),
+            Err(error) => result = Err(error),
+        }
+    }
+    if options.clipboard && result.is_ok() {
+        match editing_area.copy_to_clipboard() {
+            Ok(()) => messages.push("Copied to the clipboard".to_string()),
+            Err(error) => result = Err(error),
+        }
+    }
+    if options.output.is_none() && !options.clipboard && !options.print_geometry {
+        match editing_area.save_to_pictures(&options.export) {
+            Ok(path) => messages.push(format!("Saved to {}", path.display())),
+            Err(error) => result = Err(error),
+        }
+    }
+
+    if let Some(command_line) = invocation.borrow().as_ref() {
+        if let Some(geometry) = &geometry {
+            command_line.print_literal(&format!("{}\n", geometry));
+        }
+        // Progress goes to stderr, stdout is kept for --print-geometry.
+        for message in &messages {
+            command_line.printerr_literal(&format!("{}\n", message));
+        }
+    }
+    match result {
+        Ok(()) => {
+            invocation.take();
+        }
+        Err(error) => fail(invocation, &error.to_string()),
+    }
+}
+
+/// Reports `error` to the invoker and makes it exit unsuccessfully.
+fn fail(invocation: &Invocation, error: &str) {
+    let Some(command_line) = invocation.take() else {
+        return;
+    };
+    command_line.printerr_literal(&format!("windshot: {}\n", error));
+    command_line.set_exit_status(1);
+    if !command_line.is_remote() {
+        FAILED.with(|failed| failed.set(true));
+    }
+}
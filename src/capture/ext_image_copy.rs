@@ -0,0 +1,394 @@
+//! Capture backend using `ext-image-copy-capture-v1`, which can capture
+//! both whole outputs and single toplevels listed by
+//! `ext-foreign-toplevel-list-v1`.
+
+use wayland_client::globals::{registry_queue_init, GlobalListContents};
+use wayland_client::protocol::wl_buffer::WlBuffer;
+use wayland_client::protocol::wl_output;
+use wayland_client::protocol::wl_registry::WlRegistry;
+use wayland_client::protocol::wl_shm::{self, WlShm};
+use wayland_client::protocol::wl_shm_pool::WlShmPool;
+use wayland_client::{
+    delegate_noop, event_created_child, Connection, Dispatch, EventQueue, Proxy, QueueHandle,
+    WEnum,
+};
+use wayland_protocols::ext::foreign_toplevel_list::v1::client::ext_foreign_toplevel_handle_v1::{
+    self, ExtForeignToplevelHandleV1,
+};
+use wayland_protocols::ext::foreign_toplevel_list::v1::client::ext_foreign_toplevel_list_v1::{
+    self, ExtForeignToplevelListV1,
+};
+use wayland_protocols::ext::image_capture_source::v1::client::ext_foreign_toplevel_image_capture_source_manager_v1::ExtForeignToplevelImageCaptureSourceManagerV1;
+use wayland_protocols::ext::image_capture_source::v1::client::ext_image_capture_source_v1::ExtImageCaptureSourceV1;
+use wayland_protocols::ext::image_capture_source::v1::client::ext_output_image_capture_source_manager_v1::ExtOutputImageCaptureSourceManagerV1;
+use wayland_protocols::ext::image_copy_capture::v1::client::ext_image_copy_capture_frame_v1::{
+    self, ExtImageCopyCaptureFrameV1,
+};
+use wayland_protocols::ext::image_copy_capture::v1::client::ext_image_copy_capture_manager_v1::{
+    self, ExtImageCopyCaptureManagerV1,
+};
+use wayland_protocols::ext::image_copy_capture::v1::client::ext_image_copy_capture_session_v1::{
+    self, ExtImageCopyCaptureSessionV1,
+};
+
+use super::wayland::{enumerate_outputs, ShmBuffer};
+use super::{CaptureBoxed, CaptureError, CapturedOutput};
+
+/// A toplevel as advertised by `ext-foreign-toplevel-list-v1`.
+#[derive(Debug, Clone)]
+pub struct Toplevel {
+    handle: ExtForeignToplevelHandleV1,
+    pub identifier: String,
+    pub title: String,
+    pub app_id: String,
+}
+
+impl Toplevel {
+    /// Checks the query against the identifier and app id exactly, and
+    /// against the title as a case-insensitive substring.
+    pub fn matches(&self, query: &str) -> bool {
+        self.identifier == query
+            || self.app_id == query
+            || self.title.to_lowercase().contains(&query.to_lowercase())
+    }
+}
+
+#[derive(Default)]
+struct Session {
+    size: Option<(u32, u32)>,
+    format: Option<wl_shm::Format>,
+    buffer: Option<ShmBuffer>,
+    frame: Option<ExtImageCopyCaptureFrameV1>,
+    y_invert: bool,
+    result: Option<Result<(), CaptureError>>,
+}
+
+struct State {
+    shm: Option<WlShm>,
+    sessions: Vec<Session>,
+    toplevels: Vec<Toplevel>,
+}
+
+impl State {
+    fn start_frame(
+        &mut self,
+        session: &ExtImageCopyCaptureSessionV1,
+        index: usize,
+        qh: &QueueHandle<Self>,
+    ) {
+        let Some(shm) = &self.shm else {
+            return;
+        };
+        let current = &mut self.sessions[index];
+        if current.frame.is_some() {
+            return;
+        }
+        let (Some((width, height)), Some(format)) = (current.size, current.format) else {
+            current.result = Some(Err(CaptureError::Failed(
+                "compositor offered no supported shm format".to_string(),
+            )));
+            return;
+        };
+        match ShmBuffer::new(shm, qh, width, height, width * 4, format) {
+            Ok(buffer) => {
+                let frame = session.create_frame(qh, index);
+                frame.attach_buffer(&buffer.buffer);
+                frame.damage_buffer(0, 0, width as i32, height as i32);
+                frame.capture();
+                current.buffer = Some(buffer);
+                current.frame = Some(frame);
+            }
+            Err(error) => current.result = Some(Err(error)),
+        }
+    }
+}
+
+/// Returns whether the compositor advertises ext-image-copy-capture along
+/// with output capture sources.
+pub fn is_available(connection: &Connection) -> bool {
+    registry_queue_init::<State>(connection).is_ok_and(|(globals, _)| {
+        globals.contents().with_list(|list| {
+            let has = |name: &str| list.iter().any(|global| global.interface == name);
+            has(ExtImageCopyCaptureManagerV1::interface().name)
+                && has(ExtOutputImageCaptureSourceManagerV1::interface().name)
+        })
+    })
+}
+
+fn fetch_toplevels(
+    globals: &wayland_client::globals::GlobalList,
+    queue: &mut EventQueue<State>,
+    state: &mut State,
+) -> Result<(), CaptureError> {
+    let qh = queue.handle();
+    let list = globals
+        .bind::<ExtForeignToplevelListV1, _, _>(&qh, 1..=1, ())
+        .map_err(|_| CaptureError::MissingProtocol("ext_foreign_toplevel_list_v1"))?;
+    // The first roundtrip announces the handles, the second their details.
+    queue.roundtrip(state)?;
+    queue.roundtrip(state)?;
+    list.stop();
+    Ok(())
+}
+
+/// Captures every output into its own texture.
+pub fn capture_all_outputs(connection: &Connection) -> Result<CaptureBoxed, CaptureError> {
+    let outputs = enumerate_outputs(connection)?;
+
+    let (globals, mut queue) = registry_queue_init::<State>(connection)?;
+    let qh = queue.handle();
+    let source_manager = globals
+        .bind::<ExtOutputImageCaptureSourceManagerV1, _, _>(&qh, 1..=1, ())
+        .map_err(|_| CaptureError::MissingProtocol("ext_output_image_capture_source_manager_v1"))?;
+
+    let sources = outputs
+        .iter()
+        .map(|info| source_manager.create_source(&info.output, &qh, ()))
+        .collect();
+    let buffers = capture_sources(&globals, &mut queue, sources)?;
+    source_manager.destroy();
+
+    let mut captured = Vec::with_capacity(outputs.len());
+    for (info, (buffer, y_invert)) in outputs.into_iter().zip(buffers) {
+        captured.push(CapturedOutput {
+            name: info.name,
+            x: info.x,
+            y: info.y,
+            width: info.width,
+            height: info.height,
+            texture: buffer.into_texture(y_invert)?,
+        });
+    }
+    Ok(captured.into())
+}
+
+/// Captures the first toplevel matching `query`, see [`Toplevel::matches`].
+///
+/// The result is a single "output" at the origin with the size of the
+/// window, since the protocols do not tell us where the window is.
+/// For the same reason we don't know the scale of its output, so the
+/// window is kept at its buffer size.
+pub fn capture_toplevel(connection: &Connection, query: &str) -> Result<CaptureBoxed, CaptureError> {
+    let (globals, mut queue) = registry_queue_init::<State>(connection)?;
+    let qh = queue.handle();
+    let mut state = State { shm: None, sessions: Vec::new(), toplevels: Vec::new() };
+    fetch_toplevels(&globals, &mut queue, &mut state)?;
+
+    let Some(toplevel) = state.toplevels.iter().find(|toplevel| toplevel.matches(query)).cloned()
+    else {
+        return Err(CaptureError::Failed(format!("no window matches {:?}", query)));
+    };
+    let source_manager = globals
+        .bind::<ExtForeignToplevelImageCaptureSourceManagerV1, _, _>(&qh, 1..=1, ())
+        .map_err(|_| {
+            CaptureError::MissingProtocol("ext_foreign_toplevel_image_capture_source_manager_v1")
+        })?;
+    let source = source_manager.create_source(&toplevel.handle, &qh, ());
+    let mut buffers = capture_sources(&globals, &mut queue, vec![source])?;
+    source_manager.destroy();
+    for toplevel in state.toplevels {
+        toplevel.handle.destroy();
+    }
+
+    let Some((buffer, y_invert)) = buffers.pop() else {
+        return Err(CaptureError::Failed("window capture produced no frame".to_string()));
+    };
+    let (width, height) = (buffer.width as i32, buffer.height as i32);
+    Ok(vec![CapturedOutput {
+        name: Some(toplevel.title),
+        x: 0,
+        y: 0,
+        width,
+        height,
+        texture: buffer.into_texture(y_invert)?,
+    }]
+    .into())
+}
+
+/// Runs one capture session per source and waits for every frame.
+/// Returns the filled buffers in the same order as the sources.
+fn capture_sources(
+    globals: &wayland_client::globals::GlobalList,
+    queue: &mut EventQueue<State>,
+    sources: Vec<ExtImageCaptureSourceV1>,
+) -> Result<Vec<(ShmBuffer, bool)>, CaptureError> {
+    let qh = queue.handle();
+    let copy_manager = globals
+        .bind::<ExtImageCopyCaptureManagerV1, _, _>(&qh, 1..=1, ())
+        .map_err(|_| CaptureError::MissingProtocol("ext_image_copy_capture_manager_v1"))?;
+    let shm = globals
+        .bind::<WlShm, _, _>(&qh, 1..=1, ())
+        .map_err(|_| CaptureError::MissingProtocol("wl_shm"))?;
+
+    let mut state = State { shm: Some(shm), sessions: Vec::new(), toplevels: Vec::new() };
+    let sessions: Vec<_> = sources
+        .iter()
+        .enumerate()
+        .map(|(index, source)| {
+            state.sessions.push(Session::default());
+            copy_manager.create_session(
+                source,
+                ext_image_copy_capture_manager_v1::Options::empty(),
+                &qh,
+                index,
+            )
+        })
+        .collect();
+
+    while state.sessions.iter().any(|session| session.result.is_none()) {
+        queue.blocking_dispatch(&mut state)?;
+    }
+
+    for session in &state.sessions {
+        if let Some(frame) = &session.frame {
+            frame.destroy();
+        }
+    }
+    for session in sessions {
+        session.destroy();
+    }
+    for source in sources {
+        source.destroy();
+    }
+    copy_manager.destroy();
+
+    let mut buffers = Vec::with_capacity(state.sessions.len());
+    for session in state.sessions {
+        session.result.unwrap_or(Ok(()))?;
+        let Some(buffer) = session.buffer else {
+            return Err(CaptureError::Failed("frame finished without a buffer".to_string()));
+        };
+        buffers.push((buffer, session.y_invert));
+    }
+    Ok(buffers)
+}
+
+impl Dispatch<WlRegistry, GlobalListContents> for State {
+    fn event(
+        _: &mut Self,
+        _: &WlRegistry,
+        _: <WlRegistry as Proxy>::Event,
+        _: &GlobalListContents,
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+    }
+}
+
+impl Dispatch<ExtImageCopyCaptureSessionV1, usize> for State {
+    fn event(
+        state: &mut Self,
+        session: &ExtImageCopyCaptureSessionV1,
+        event: ext_image_copy_capture_session_v1::Event,
+        index: &usize,
+        _: &Connection,
+        qh: &QueueHandle<Self>,
+    ) {
+        let index = *index;
+        match event {
+            ext_image_copy_capture_session_v1::Event::BufferSize { width, height } => {
+                state.sessions[index].size = Some((width, height));
+            }
+            ext_image_copy_capture_session_v1::Event::ShmFormat { format: WEnum::Value(format) } => {
+                let current = &mut state.sessions[index];
+                if current.format.is_none() && ShmBuffer::is_supported_format(format) {
+                    current.format = Some(format);
+                }
+            }
+            ext_image_copy_capture_session_v1::Event::Done => state.start_frame(session, index, qh),
+            ext_image_copy_capture_session_v1::Event::Stopped => {
+                state.sessions[index].result.get_or_insert(Err(CaptureError::Failed(
+                    "capture source went away".to_string(),
+                )));
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Dispatch<ExtImageCopyCaptureFrameV1, usize> for State {
+    fn event(
+        state: &mut Self,
+        _: &ExtImageCopyCaptureFrameV1,
+        event: ext_image_copy_capture_frame_v1::Event,
+        index: &usize,
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        let current = &mut state.sessions[*index];
+        match event {
+            ext_image_copy_capture_frame_v1::Event::Transform { transform } => {
+                current.y_invert = transform == WEnum::Value(wl_output::Transform::Flipped180);
+            }
+            ext_image_copy_capture_frame_v1::Event::Ready => {
+                current.result = Some(Ok(()));
+            }
+            ext_image_copy_capture_frame_v1::Event::Failed { reason } => {
+                current.result = Some(Err(CaptureError::Failed(format!(
+                    "compositor failed to copy the frame: {:?}",
+                    reason
+                ))));
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Dispatch<ExtForeignToplevelListV1, ()> for State {
+    fn event(
+        state: &mut Self,
+        _: &ExtForeignToplevelListV1,
+        event: ext_foreign_toplevel_list_v1::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        if let ext_foreign_toplevel_list_v1::Event::Toplevel { toplevel } = event {
+            state.toplevels.push(Toplevel {
+                handle: toplevel,
+                identifier: String::new(),
+                title: String::new(),
+                app_id: String::new(),
+            });
+        }
+    }
+
+    event_created_child!(State, ExtForeignToplevelListV1, [
+        ext_foreign_toplevel_list_v1::EVT_TOPLEVEL_OPCODE => (ExtForeignToplevelHandleV1, ()),
+    ]);
+}
+
+impl Dispatch<ExtForeignToplevelHandleV1, ()> for State {
+    fn event(
+        state: &mut Self,
+        handle: &ExtForeignToplevelHandleV1,
+        event: ext_foreign_toplevel_handle_v1::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        let Some(toplevel) = state.toplevels.iter_mut().find(|toplevel| &toplevel.handle == handle)
+        else {
+            return;
+        };
+        match event {
+            ext_foreign_toplevel_handle_v1::Event::Identifier { identifier } => {
+                toplevel.identifier = identifier;
+            }
+            ext_foreign_toplevel_handle_v1::Event::Title { title } => toplevel.title = title,
+            ext_foreign_toplevel_handle_v1::Event::AppId { app_id } => toplevel.app_id = app_id,
+            ext_foreign_toplevel_handle_v1::Event::Closed => {
+                state.toplevels.retain(|toplevel| &toplevel.handle != handle);
+            }
+            _ => {}
+        }
+    }
+}
+
+delegate_noop!(State: ignore WlShm);
+delegate_noop!(State: ignore WlShmPool);
+delegate_noop!(State: ignore WlBuffer);
+delegate_noop!(State: ExtImageCopyCaptureManagerV1);
+delegate_noop!(State: ExtOutputImageCaptureSourceManagerV1);
+delegate_noop!(State: ExtForeignToplevelImageCaptureSourceManagerV1);
+delegate_noop!(State: ExtImageCaptureSourceV1);
@@ -0,0 +1,259 @@
+mod ext_image_copy;
+mod portal;
+mod wayland;
+mod windows;
+mod wlr_screencopy;
+
+pub use windows::{visible_windows, WindowGeometry};
+
+use std::fmt;
+use std::ops::{Deref, DerefMut};
+
+use gtk4::prelude::{SnapshotExt, TextureExt};
+
+/// The frozen contents of one output.
+#[derive(Debug, Clone)]
+pub struct CapturedOutput {
+    pub name: Option<String>,
+    /// Logical position of the output in the compositor's global space.
+    pub x: i32,
+    pub y: i32,
+    /// Logical size of the output.
+    pub width: i32,
+    pub height: i32,
+    /// The captured pixels, in buffer (not logical) size.
+    pub texture: gdk4::Texture,
+}
+
+impl CapturedOutput {
+    /// The number of texture pixels per logical pixel.
+    pub fn scale(&self) -> f64 {
+        if self.width <= 0 {
+            return 1.0;
+        }
+        self.texture.width() as f64 / self.width as f64
+    }
+
+    /// Whether the logical `point`, in global space, is on this output.
+    pub fn contains(&self, point: (f32, f32)) -> bool {
+        let (x, y) = point;
+        x >= self.x as f32
+            && y >= self.y as f32
+            && x < (self.x + self.width) as f32
+            && y < (self.y + self.height) as f32
+    }
+
+    /// Draws the output where it sits relative to `origin`, which is the
+    /// logical position of the top-left corner of the widget.
+    pub fn draw_to_snapshot(&self, snapshot: &gtk4::Snapshot, origin: (f32, f32)) {
+        let bounds = graphene::Rect::new(
+            self.x as f32 - origin.0,
+            self.y as f32 - origin.1,
+            self.width as f32,
+            self.height as f32,
+        );
+        snapshot.append_texture(&self.texture, &bounds);
+    }
+}
+
+#[derive(Debug, Clone, Default, glib::Boxed)]
+#[boxed_type(name = "CaptureBoxed")]
+pub struct CaptureBoxed(Vec<CapturedOutput>);
+
+impl CaptureBoxed {
+    /// The logical bounding box of every output as (x, y, width, height).
+    pub fn bounds(&self) -> Option<(i32, i32, i32, i32)> {
+        let left = self.iter().map(|output| output.x).min()?;
+        let top = self.iter().map(|output| output.y).min()?;
+        let right = self.iter().map(|output| output.x + output.width).max()?;
+        let bottom = self.iter().map(|output| output.y + output.height).max()?;
+        Some((left, top, right - left, bottom - top))
+    }
+
+    /// The highest scale of all outputs, so rendering at it loses no pixels.
+    pub fn max_scale(&self) -> f64 {
+        self.iter().map(CapturedOutput::scale).fold(1.0, f64::max)
+    }
+
+    /// Draws every captured output, the bottom layer of the editing area.
+    pub fn draw_to_snapshot(&self, snapshot: &gtk4::Snapshot, origin: (f32, f32)) {
+        for output in self.iter() {
+            output.draw_to_snapshot(snapshot, origin);
+        }
+    }
+}
+
+impl Deref for CaptureBoxed {
+    type Target = Vec<CapturedOutput>;
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl DerefMut for CaptureBoxed {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
+
+impl From<CaptureBoxed> for Vec<CapturedOutput> {
+    fn from(capture: CaptureBoxed) -> Self {
+        capture.0
+    }
+}
+
+impl From<Vec<CapturedOutput>> for CaptureBoxed {
+    fn from(outputs: Vec<CapturedOutput>) -> Self {
+        CaptureBoxed(outputs)
+    }
+}
+
+#[derive(Debug)]
+pub enum CaptureError {
+    /// Could not talk to the compositor at all.
+    Connect(String),
+    /// The compositor does not implement a protocol the backend needs.
+    MissingProtocol(&'static str),
+    Io(std::io::Error),
+    /// The compositor refused or failed to hand over the image.
+    Failed(String),
+}
+
+impl fmt::Display for CaptureError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CaptureError::Connect(error) => write!(f, "could not connect to the compositor: {}", error),
+            CaptureError::MissingProtocol(protocol) => {
+                write!(f, "the compositor does not support {}", protocol)
+            }
+            CaptureError::Io(error) => write!(f, "I/O error while capturing: {}", error),
+            CaptureError::Failed(reason) => write!(f, "capture failed: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for CaptureError {}
+
+impl From<std::io::Error> for CaptureError {
+    fn from(error: std::io::Error) -> Self {
+        CaptureError::Io(error)
+    }
+}
+
+impl From<glib::Error> for CaptureError {
+    fn from(error: glib::Error) -> Self {
+        CaptureError::Failed(error.message().to_string())
+    }
+}
+
+impl From<wayland_client::ConnectError> for CaptureError {
+    fn from(error: wayland_client::ConnectError) -> Self {
+        CaptureError::Connect(error.to_string())
+    }
+}
+
+impl From<wayland_client::globals::GlobalError> for CaptureError {
+    fn from(error: wayland_client::globals::GlobalError) -> Self {
+        CaptureError::Connect(error.to_string())
+    }
+}
+
+impl From<wayland_client::DispatchError> for CaptureError {
+    fn from(error: wayland_client::DispatchError) -> Self {
+        CaptureError::Failed(error.to_string())
+    }
+}
+
+/// Overrides the automatic backend choice, see [`CaptureBackend::from_name`].
+pub const CAPTURE_BACKEND_ENV: &str = "WINDSHOT_CAPTURE_BACKEND";
+
+/// The different ways we know of getting pixels off the screen.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CaptureBackend {
+    /// `ext-image-copy-capture-v1`, the only one able to capture single windows.
+    ExtImageCopy,
+    /// `zwlr_screencopy_manager_v1`, on wlroots based compositors.
+    WlrScreencopy,
+    /// `org.freedesktop.portal.Screenshot` over D-Bus, everywhere else.
+    Portal,
+}
+
+impl CaptureBackend {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "ext" | "ext-image-copy" => Some(CaptureBackend::ExtImageCopy),
+            "wlr" | "wlr-screencopy" => Some(CaptureBackend::WlrScreencopy),
+            "portal" => Some(CaptureBackend::Portal),
+            _ => None,
+        }
+    }
+
+    /// Picks the backend for the running session.
+    ///
+    /// The environment variable wins if set, then native capture is
+    /// preferred since it does not depend on a portal being installed,
+    /// the newer ext protocols first.
+    pub fn detect() -> Self {
+        if let Some(backend) = std::env::var(CAPTURE_BACKEND_ENV)
+            .ok()
+            .and_then(|name| Self::from_name(&name))
+        {
+            return backend;
+        }
+
+        let connection = wayland_client::Connection::connect_to_env().ok();
+        match connection {
+            Some(connection) if ext_image_copy::is_available(&connection) => {
+                CaptureBackend::ExtImageCopy
+            }
+            Some(connection) if wlr_screencopy::is_available(&connection) => {
+                CaptureBackend::WlrScreencopy
+            }
+            _ => CaptureBackend::Portal,
+        }
+    }
+
+    /// Captures what `target` points at.
+    pub fn capture(self, target: &CaptureTarget) -> Result<CaptureBoxed, CaptureError> {
+        match target {
+            CaptureTarget::AllOutputs => self.capture_all_outputs(),
+            CaptureTarget::Window(query) => self.capture_window(query),
+        }
+    }
+
+    /// Freezes the screen: captures every output of the current session.
+    pub fn capture_all_outputs(self) -> Result<CaptureBoxed, CaptureError> {
+        match self {
+            CaptureBackend::ExtImageCopy => {
+                let connection = wayland_client::Connection::connect_to_env()?;
+                ext_image_copy::capture_all_outputs(&connection)
+            }
+            CaptureBackend::WlrScreencopy => {
+                let connection = wayland_client::Connection::connect_to_env()?;
+                wlr_screencopy::capture_all_outputs(&connection)
+            }
+            CaptureBackend::Portal => portal::capture_screen(),
+        }
+    }
+
+    /// Captures a single window, matched by identifier, app id or title.
+    pub fn capture_window(self, query: &str) -> Result<CaptureBoxed, CaptureError> {
+        match self {
+            CaptureBackend::ExtImageCopy => {
+                let connection = wayland_client::Connection::connect_to_env()?;
+                ext_image_copy::capture_toplevel(&connection, query)
+            }
+            _ => Err(CaptureError::MissingProtocol("ext-image-copy-capture-v1")),
+        }
+    }
+}
+
+/// What to capture.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CaptureTarget {
+    /// Every output, to select a region from.
+    AllOutputs,
+    /// A single window, see [`CaptureBackend::capture_window`].
+    Window(String),
+}
+
@@ -0,0 +1,193 @@
+//! Capture backend going through `org.freedesktop.portal.Screenshot`, for
+//! compositors that do not let clients copy outputs themselves (GNOME, KDE).
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use gtk4::prelude::*;
+
+use super::{CaptureBoxed, CaptureError, CapturedOutput};
+
+const PORTAL_BUS_NAME: &str = "org.freedesktop.portal.Desktop";
+const PORTAL_OBJECT_PATH: &str = "/org/freedesktop/portal/desktop";
+const SCREENSHOT_INTERFACE: &str = "org.freedesktop.portal.Screenshot";
+const REQUEST_INTERFACE: &str = "org.freedesktop.portal.Request";
+const DBUS_BUS_NAME: &str = "org.freedesktop.DBus";
+const DBUS_OBJECT_PATH: &str = "/org/freedesktop/DBus";
+/// How long to wait for the portal's answer. Generous, some portals ask
+/// the user for permission the first time.
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// The portal's answer, or why it will never come. Shared with the timeout
+/// source, which needs it to be `Send`.
+type Response = Arc<Mutex<Option<Result<(u32, HashMap<String, glib::Variant>), CaptureError>>>>;
+
+/// Asks the portal for a screenshot of the whole desktop.
+///
+/// The portal hands back a single image, so the capture contains one
+/// output spanning the bounding box of every monitor GDK knows about.
+pub fn capture_screen() -> Result<CaptureBoxed, CaptureError> {
+    // The response comes in as a signal, so we need a main context to
+    // dispatch it while blocking the caller.
+    let context = glib::MainContext::new();
+    context
+        .with_thread_default(|| request_screenshot(&context))
+        .map_err(|error| CaptureError::Failed(error.to_string()))?
+}
+
+fn request_screenshot(context: &glib::MainContext) -> Result<CaptureBoxed, CaptureError> {
+    let connection = gio::bus_get_sync(gio::BusType::Session, gio::Cancellable::NONE)?;
+
+    let Some(unique_name) = connection.unique_name() else {
+        return Err(CaptureError::Connect("session bus connection has no name".to_string()));
+    };
+    let token = format!("windshot{}", std::process::id());
+    // Subscribing before making the call means the response cannot be
+    // missed, the handle path is predictable from the token.
+    let sender = unique_name.trim_start_matches(':').replace('.', "_");
+    let expected_handle = format!("{}/request/{}/{}", PORTAL_OBJECT_PATH, sender, token);
+
+    let response: Response = Arc::default();
+    let mut subscription = subscribe_response(&connection, &expected_handle, &response);
+    // A portal that crashes or restarts forgets the request.
+    let owner_subscription = subscribe_owner_lost(&connection, &response);
+
+    let options = glib::VariantDict::new(None);
+    options.insert("handle_token", token.as_str());
+    options.insert("interactive", false);
+    let parameters = glib::Variant::tuple_from_iter(["".to_variant(), options.end()]);
+
+    let reply = connection.call_sync(
+        Some(PORTAL_BUS_NAME),
+        PORTAL_OBJECT_PATH,
+        SCREENSHOT_INTERFACE,
+        "Screenshot",
+        Some(&parameters),
+        Some(glib::VariantTy::new("(o)").expect("valid variant type")),
+        gio::DBusCallFlags::NONE,
+        -1,
+        gio::Cancellable::NONE,
+    )?;
+    let handle = reply
+        .child_value(0)
+        .str()
+        .map(str::to_string)
+        .unwrap_or_default();
+    // Old portal versions ignore handle_token.
+    if handle != expected_handle {
+        connection.signal_unsubscribe(subscription);
+        subscription = subscribe_response(&connection, &handle, &response);
+    }
+
+    let timeout = {
+        let response = response.clone();
+        let name = Some("portal response timeout");
+        glib::timeout_source_new(RESPONSE_TIMEOUT, name, glib::Priority::DEFAULT, move || {
+            let message = "portal did not answer the screenshot request".to_string();
+            answer(&response, Err(CaptureError::Failed(message)));
+            glib::ControlFlow::Break
+        })
+    };
+    timeout.attach(Some(context));
+
+    while response.lock().expect("response lock poisoned").is_none() {
+        context.iteration(true);
+    }
+    timeout.destroy();
+    connection.signal_unsubscribe(owner_subscription);
+    connection.signal_unsubscribe(subscription);
+
+    let Some(received) = response.lock().expect("response lock poisoned").take() else {
+        unreachable!("loop above only exits once a response arrived");
+    };
+    let (code, results) = received?;
+    match code {
+        0 => {}
+        1 => return Err(CaptureError::Failed("screenshot was cancelled".to_string())),
+        _ => return Err(CaptureError::Failed("portal could not take a screenshot".to_string())),
+    }
+    let Some(uri) = results.get("uri").and_then(|uri| uri.get::<String>()) else {
+        return Err(CaptureError::Failed("portal response did not contain an uri".to_string()));
+    };
+
+    let texture = gdk4::Texture::from_file(&gio::File::for_uri(&uri))?;
+    let (x, y, width, height) = desktop_bounds().unwrap_or((0, 0, texture.width(), texture.height()));
+
+    Ok(vec![CapturedOutput {
+        name: None,
+        x,
+        y,
+        width,
+        height,
+        texture,
+    }]
+    .into())
+}
+
+/// Stores `result` unless an earlier answer, or error, is already there.
+fn answer(response: &Response, result: Result<(u32, HashMap<String, glib::Variant>), CaptureError>) {
+    let mut response = response.lock().expect("response lock poisoned");
+    if response.is_none() {
+        *response = Some(result);
+    }
+}
+
+fn subscribe_response(
+    connection: &gio::DBusConnection,
+    handle: &str,
+    response: &Response,
+) -> gio::SignalSubscriptionId {
+    let response = response.clone();
+    connection.signal_subscribe(
+        Some(PORTAL_BUS_NAME),
+        Some(REQUEST_INTERFACE),
+        Some("Response"),
+        Some(handle),
+        None,
+        gio::DBusSignalFlags::NONE,
+        move |_, _, _, _, _, parameters| {
+            if let Some(parameters) = parameters.get() {
+                answer(&response, Ok(parameters));
+            }
+        },
+    )
+}
+
+/// Gives up on the response once the portal's bus name loses its owner.
+fn subscribe_owner_lost(connection: &gio::DBusConnection, response: &Response) -> gio::SignalSubscriptionId {
+    let response = response.clone();
+    connection.signal_subscribe(
+        Some(DBUS_BUS_NAME),
+        Some(DBUS_BUS_NAME),
+        Some("NameOwnerChanged"),
+        Some(DBUS_OBJECT_PATH),
+        Some(PORTAL_BUS_NAME),
+        gio::DBusSignalFlags::NONE,
+        move |_, _, _, _, _, parameters| {
+            let Some((_, old_owner, _)) = parameters.get::<(String, String, String)>() else {
+                return;
+            };
+            if !old_owner.is_empty() {
+                let message = "portal went away before answering".to_string();
+                answer(&response, Err(CaptureError::Failed(message)));
+            }
+        },
+    )
+}
+
+/// The logical bounding box of every monitor as (x, y, width, height).
+fn desktop_bounds() -> Option<(i32, i32, i32, i32)> {
+    let monitors = gdk4::Display::default()?.monitors();
+    let mut bounds: Option<(i32, i32, i32, i32)> = None;
+    for monitor in monitors.iter::<gdk4::Monitor>().flatten() {
+        let geometry = monitor.geometry();
+        let (left, top) = (geometry.x(), geometry.y());
+        let (right, bottom) = (left + geometry.width(), top + geometry.height());
+        bounds = Some(match bounds {
+            Some((x0, y0, x1, y1)) => (x0.min(left), y0.min(top), x1.max(right), y1.max(bottom)),
+            None => (left, top, right, bottom),
+        });
+    }
+    bounds.map(|(x0, y0, x1, y1)| (x0, y0, x1 - x0, y1 - y0))
+}
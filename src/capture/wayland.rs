@@ -0,0 +1,263 @@
+//! Plumbing shared by the capture backends that speak Wayland directly.
+
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::os::fd::AsFd;
+
+use glib::prelude::Cast;
+use wayland_client::globals::{registry_queue_init, GlobalListContents};
+use wayland_client::protocol::wl_buffer::WlBuffer;
+use wayland_client::protocol::wl_output::{self, WlOutput};
+use wayland_client::protocol::wl_registry::WlRegistry;
+use wayland_client::protocol::wl_shm::{self, WlShm};
+use wayland_client::protocol::wl_shm_pool::WlShmPool;
+use wayland_client::{Connection, Dispatch, Proxy, QueueHandle};
+use wayland_protocols::xdg::xdg_output::zv1::client::zxdg_output_manager_v1::ZxdgOutputManagerV1;
+use wayland_protocols::xdg::xdg_output::zv1::client::zxdg_output_v1::{self, ZxdgOutputV1};
+
+use super::CaptureError;
+
+/// An output as advertised by the compositor, in logical coordinates.
+#[derive(Debug, Clone)]
+pub struct OutputInfo {
+    pub output: WlOutput,
+    pub name: Option<String>,
+    pub x: i32,
+    pub y: i32,
+    pub width: i32,
+    pub height: i32,
+    pub scale: i32,
+}
+
+#[derive(Default)]
+struct OutputsState {
+    outputs: Vec<OutputInfo>,
+    /// Current mode of each output in buffer pixels, used when xdg-output
+    /// is not available to compute the logical size.
+    modes: Vec<(i32, i32)>,
+}
+
+/// Lists every output along with its logical geometry.
+///
+/// Uses xdg-output when the compositor has it, otherwise derives the
+/// geometry from `wl_output` alone, which ignores transforms.
+pub fn enumerate_outputs(connection: &Connection) -> Result<Vec<OutputInfo>, CaptureError> {
+    let (globals, mut queue) = registry_queue_init::<OutputsState>(connection)?;
+    let qh = queue.handle();
+    let mut state = OutputsState::default();
+
+    let output_globals: Vec<_> = globals
+        .contents()
+        .clone_list()
+        .into_iter()
+        .filter(|global| global.interface == WlOutput::interface().name)
+        .collect();
+
+    for (index, global) in output_globals.iter().enumerate() {
+        let output = globals
+            .registry()
+            .bind::<WlOutput, _, _>(global.name, global.version.min(4), &qh, index);
+        state.outputs.push(OutputInfo {
+            output,
+            name: None,
+            x: 0,
+            y: 0,
+            width: 0,
+            height: 0,
+            scale: 1,
+        });
+        state.modes.push((0, 0));
+    }
+
+    // Gets the wl_output events in, including the mode and scale.
+    queue.roundtrip(&mut state)?;
+
+    if let Ok(manager) = globals.bind::<ZxdgOutputManagerV1, _, _>(&qh, 2..=3, ()) {
+        for (index, info) in state.outputs.iter().enumerate() {
+            manager.get_xdg_output(&info.output, &qh, index);
+        }
+        queue.roundtrip(&mut state)?;
+        manager.destroy();
+    } else {
+        for (info, (mode_width, mode_height)) in state.outputs.iter_mut().zip(&state.modes) {
+            info.width = mode_width / info.scale.max(1);
+            info.height = mode_height / info.scale.max(1);
+        }
+    }
+
+    Ok(state.outputs)
+}
+
+impl Dispatch<WlRegistry, GlobalListContents> for OutputsState {
+    fn event(
+        _: &mut Self,
+        _: &WlRegistry,
+        _: <WlRegistry as Proxy>::Event,
+        _: &GlobalListContents,
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+    }
+}
+
+impl Dispatch<WlOutput, usize> for OutputsState {
+    fn event(
+        state: &mut Self,
+        _: &WlOutput,
+        event: wl_output::Event,
+        index: &usize,
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        let info = &mut state.outputs[*index];
+        match event {
+            wl_output::Event::Geometry { x, y, .. } => {
+                info.x = x;
+                info.y = y;
+            }
+            wl_output::Event::Mode { flags, width, height, .. } => {
+                let current = flags
+                    .into_result()
+                    .is_ok_and(|flags| flags.contains(wl_output::Mode::Current));
+                if current {
+                    state.modes[*index] = (width, height);
+                }
+            }
+            wl_output::Event::Scale { factor } => info.scale = factor,
+            wl_output::Event::Name { name } => info.name = Some(name),
+            _ => {}
+        }
+    }
+}
+
+impl Dispatch<ZxdgOutputManagerV1, ()> for OutputsState {
+    fn event(
+        _: &mut Self,
+        _: &ZxdgOutputManagerV1,
+        _: <ZxdgOutputManagerV1 as Proxy>::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+    }
+}
+
+impl Dispatch<ZxdgOutputV1, usize> for OutputsState {
+    fn event(
+        state: &mut Self,
+        _: &ZxdgOutputV1,
+        event: zxdg_output_v1::Event,
+        index: &usize,
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        let info = &mut state.outputs[*index];
+        match event {
+            zxdg_output_v1::Event::LogicalPosition { x, y } => {
+                info.x = x;
+                info.y = y;
+            }
+            zxdg_output_v1::Event::LogicalSize { width, height } => {
+                info.width = width;
+                info.height = height;
+            }
+            zxdg_output_v1::Event::Name { name } => {
+                info.name.get_or_insert(name);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// A `wl_buffer` backed by a memfd that the compositor copies a frame into.
+pub struct ShmBuffer {
+    file: File,
+    pool: WlShmPool,
+    pub buffer: WlBuffer,
+    pub width: u32,
+    pub height: u32,
+    pub stride: u32,
+    pub format: wl_shm::Format,
+}
+
+impl ShmBuffer {
+    pub fn new<D>(
+        shm: &WlShm,
+        qh: &QueueHandle<D>,
+        width: u32,
+        height: u32,
+        stride: u32,
+        format: wl_shm::Format,
+    ) -> Result<Self, CaptureError>
+    where
+        D: Dispatch<WlShmPool, ()> + Dispatch<WlBuffer, ()> + 'static,
+    {
+        let size = stride as usize * height as usize;
+        let fd = rustix::fs::memfd_create("windshot-capture", rustix::fs::MemfdFlags::CLOEXEC)
+            .map_err(std::io::Error::from)?;
+        let file = File::from(fd);
+        file.set_len(size as u64)?;
+
+        let pool = shm.create_pool(file.as_fd(), size as i32, qh, ());
+        let buffer = pool.create_buffer(
+            0,
+            width as i32,
+            height as i32,
+            stride as i32,
+            format,
+            qh,
+            (),
+        );
+
+        Ok(ShmBuffer { file, pool, buffer, width, height, stride, format })
+    }
+
+    /// Whether the capture code knows how to turn this format into a texture.
+    pub const fn is_supported_format(format: wl_shm::Format) -> bool {
+        memory_format(format).is_some()
+    }
+
+    /// Reads the copied frame back and turns it into a texture, flipping it
+    /// if the compositor said the frame is upside down.
+    pub fn into_texture(mut self, y_invert: bool) -> Result<gdk4::Texture, CaptureError> {
+        let stride = self.stride as usize;
+        let mut data = vec![0u8; stride * self.height as usize];
+        self.file.seek(SeekFrom::Start(0))?;
+        self.file.read_exact(&mut data)?;
+
+        self.buffer.destroy();
+        self.pool.destroy();
+
+        if y_invert {
+            let rows: Vec<&[u8]> = data.chunks_exact(stride).rev().collect();
+            data = rows.concat();
+        }
+
+        let Some(memory_format) = memory_format(self.format) else {
+            return Err(CaptureError::Failed(format!(
+                "unsupported shm format {:?}",
+                self.format
+            )));
+        };
+        let bytes = glib::Bytes::from_owned(data);
+        let texture = gdk4::MemoryTexture::new(
+            self.width as i32,
+            self.height as i32,
+            memory_format,
+            &bytes,
+            stride,
+        );
+        Ok(texture.upcast())
+    }
+}
+
+/// `wl_shm` formats are little-endian, GDK memory formats are in byte order.
+const fn memory_format(format: wl_shm::Format) -> Option<gdk4::MemoryFormat> {
+    match format {
+        wl_shm::Format::Argb8888 => Some(gdk4::MemoryFormat::B8g8r8a8Premultiplied),
+        wl_shm::Format::Xrgb8888 => Some(gdk4::MemoryFormat::B8g8r8x8),
+        wl_shm::Format::Abgr8888 => Some(gdk4::MemoryFormat::R8g8b8a8Premultiplied),
+        wl_shm::Format::Xbgr8888 => Some(gdk4::MemoryFormat::R8g8b8x8),
+        _ => None,
+    }
+}
@@ -0,0 +1,93 @@
+//! Where the visible windows are, for snapping the selection to them.
+//!
+//! Neither `ext-foreign-toplevel-list-v1` nor `wlr-foreign-toplevel-management`
+//! tell clients where toplevels are, only that they exist. The geometry
+//! comes from sway's IPC instead, when we run under sway.
+
+use std::io::{Read, Write};
+use std::os::unix::net::UnixStream;
+
+use super::CaptureError;
+
+const SWAY_SOCKET_ENV: &str = "SWAYSOCK";
+const IPC_MAGIC: &[u8; 6] = b"i3-ipc";
+const IPC_GET_TREE: u32 = 4;
+
+/// A visible window, in the compositor's global logical space.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WindowGeometry {
+    pub x: i32,
+    pub y: i32,
+    pub width: i32,
+    pub height: i32,
+}
+
+/// Lists the visible windows, or nothing when the compositor cannot tell.
+pub fn visible_windows() -> Vec<WindowGeometry> {
+    if std::env::var_os(SWAY_SOCKET_ENV).is_none() {
+        return Vec::new();
+    }
+    match sway_windows() {
+        Ok(windows) => windows,
+        Err(error) => {
+            eprintln!("Could not list windows from sway: {}", error);
+            Vec::new()
+        }
+    }
+}
+
+fn sway_windows() -> Result<Vec<WindowGeometry>, CaptureError> {
+    let path = std::env::var_os(SWAY_SOCKET_ENV)
+        .ok_or_else(|| CaptureError::Connect(format!("{} is not set", SWAY_SOCKET_ENV)))?;
+    let mut stream = UnixStream::connect(path)?;
+
+    let mut request = IPC_MAGIC.to_vec();
+    request.extend_from_slice(&0u32.to_ne_bytes());
+    request.extend_from_slice(&IPC_GET_TREE.to_ne_bytes());
+    stream.write_all(&request)?;
+
+    let mut header = [0u8; 14];
+    stream.read_exact(&mut header)?;
+    if &header[..6] != IPC_MAGIC {
+        return Err(CaptureError::Failed("unexpected reply from sway".to_string()));
+    }
+    let length = u32::from_ne_bytes([header[6], header[7], header[8], header[9]]) as usize;
+    let mut payload = vec![0u8; length];
+    stream.read_exact(&mut payload)?;
+
+    let tree: serde_json::Value = serde_json::from_slice(&payload)
+        .map_err(|error| CaptureError::Failed(error.to_string()))?;
+    let mut windows = Vec::new();
+    collect_windows(&tree, &mut windows);
+    Ok(windows)
+}
+
+/// Walks the tree, keeping the visible nodes that hold an actual window.
+fn collect_windows(node: &serde_json::Value, windows: &mut Vec<WindowGeometry>) {
+    let is_window = node.get("pid").is_some_and(|pid| !pid.is_null());
+    let visible = node.get("visible").and_then(serde_json::Value::as_bool) == Some(true);
+    if is_window && visible {
+        let int = |rect: &str, key: &str| {
+            node.get(rect)
+                .and_then(|rect| rect.get(key))
+                .and_then(serde_json::Value::as_i64)
+                .unwrap_or(0) as i32
+        };
+        // `rect` includes the decorations, `window_rect` is the content
+        // relative to it, which is what people want to capture.
+        windows.push(WindowGeometry {
+            x: int("rect", "x") + int("window_rect", "x"),
+            y: int("rect", "y") + int("window_rect", "y"),
+            width: int("window_rect", "width"),
+            height: int("window_rect", "height"),
+        });
+    }
+
+    for children in ["nodes", "floating_nodes"] {
+        if let Some(children) = node.get(children).and_then(serde_json::Value::as_array) {
+            for child in children {
+                collect_windows(child, windows);
+            }
+        }
+    }
+}
@@ -0,0 +1,172 @@
+//! Capture backend using `zwlr_screencopy_manager_v1`, found on wlroots
+//! based compositors (sway, river, Hyprland, ...).
+
+use wayland_client::globals::{registry_queue_init, GlobalListContents};
+use wayland_client::protocol::wl_buffer::WlBuffer;
+use wayland_client::protocol::wl_registry::WlRegistry;
+use wayland_client::protocol::wl_shm::{self, WlShm};
+use wayland_client::protocol::wl_shm_pool::WlShmPool;
+use wayland_client::{delegate_noop, Connection, Dispatch, Proxy, QueueHandle, WEnum};
+use wayland_protocols_wlr::screencopy::v1::client::zwlr_screencopy_frame_v1::{
+    self, ZwlrScreencopyFrameV1,
+};
+use wayland_protocols_wlr::screencopy::v1::client::zwlr_screencopy_manager_v1::ZwlrScreencopyManagerV1;
+
+use super::wayland::{enumerate_outputs, ShmBuffer};
+use super::{CaptureBoxed, CaptureError, CapturedOutput};
+
+#[derive(Default)]
+struct Frame {
+    /// The first shm buffer description we know how to read.
+    buffer_info: Option<(wl_shm::Format, u32, u32, u32)>,
+    buffer: Option<ShmBuffer>,
+    y_invert: bool,
+    result: Option<Result<(), CaptureError>>,
+}
+
+struct State {
+    shm: WlShm,
+    frames: Vec<Frame>,
+}
+
+impl State {
+    fn start_copy(
+        &mut self,
+        frame: &ZwlrScreencopyFrameV1,
+        index: usize,
+        qh: &QueueHandle<Self>,
+    ) {
+        let Some((format, width, height, stride)) = self.frames[index].buffer_info else {
+            self.frames[index].result = Some(Err(CaptureError::Failed(
+                "compositor offered no supported shm format".to_string(),
+            )));
+            return;
+        };
+        match ShmBuffer::new(&self.shm, qh, width, height, stride, format) {
+            Ok(buffer) => {
+                frame.copy(&buffer.buffer);
+                self.frames[index].buffer = Some(buffer);
+            }
+            Err(error) => self.frames[index].result = Some(Err(error)),
+        }
+    }
+}
+
+/// Returns whether the compositor advertises wlr-screencopy.
+pub fn is_available(connection: &Connection) -> bool {
+    registry_queue_init::<State>(connection).is_ok_and(|(globals, _)| {
+        globals.contents().with_list(|list| {
+            list.iter()
+                .any(|global| global.interface == ZwlrScreencopyManagerV1::interface().name)
+        })
+    })
+}
+
+/// Captures every output into its own texture.
+pub fn capture_all_outputs(connection: &Connection) -> Result<CaptureBoxed, CaptureError> {
+    let outputs = enumerate_outputs(connection)?;
+
+    let (globals, mut queue) = registry_queue_init::<State>(connection)?;
+    let qh = queue.handle();
+
+    let manager = globals
+        .bind::<ZwlrScreencopyManagerV1, _, _>(&qh, 1..=3, ())
+        .map_err(|_| CaptureError::MissingProtocol("zwlr_screencopy_manager_v1"))?;
+    let shm = globals
+        .bind::<WlShm, _, _>(&qh, 1..=1, ())
+        .map_err(|_| CaptureError::MissingProtocol("wl_shm"))?;
+
+    let mut state = State { shm, frames: Vec::new() };
+    let frames: Vec<_> = outputs
+        .iter()
+        .enumerate()
+        .map(|(index, info)| {
+            state.frames.push(Frame::default());
+            manager.capture_output(0, &info.output, &qh, index)
+        })
+        .collect();
+
+    while state.frames.iter().any(|frame| frame.result.is_none()) {
+        queue.blocking_dispatch(&mut state)?;
+    }
+
+    for frame in frames {
+        frame.destroy();
+    }
+    manager.destroy();
+
+    let mut captured = Vec::with_capacity(outputs.len());
+    for (info, frame) in outputs.into_iter().zip(state.frames) {
+        frame.result.unwrap_or(Ok(()))?;
+        let Some(buffer) = frame.buffer else {
+            return Err(CaptureError::Failed("frame finished without a buffer".to_string()));
+        };
+        captured.push(CapturedOutput {
+            name: info.name,
+            x: info.x,
+            y: info.y,
+            width: info.width,
+            height: info.height,
+            texture: buffer.into_texture(frame.y_invert)?,
+        });
+    }
+    Ok(captured.into())
+}
+
+impl Dispatch<WlRegistry, GlobalListContents> for State {
+    fn event(
+        _: &mut Self,
+        _: &WlRegistry,
+        _: <WlRegistry as Proxy>::Event,
+        _: &GlobalListContents,
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+    }
+}
+
+impl Dispatch<ZwlrScreencopyFrameV1, usize> for State {
+    fn event(
+        state: &mut Self,
+        frame: &ZwlrScreencopyFrameV1,
+        event: zwlr_screencopy_frame_v1::Event,
+        index: &usize,
+        _: &Connection,
+        qh: &QueueHandle<Self>,
+    ) {
+        let index = *index;
+        match event {
+            zwlr_screencopy_frame_v1::Event::Buffer { format: WEnum::Value(format), width, height, stride } => {
+                let current = &mut state.frames[index];
+                if current.buffer_info.is_none() && ShmBuffer::is_supported_format(format) {
+                    current.buffer_info = Some((format, width, height, stride));
+                }
+                // Before version 3 there is no buffer_done, the shm buffer
+                // event is the only one we are going to get.
+                if frame.version() < 3 {
+                    state.start_copy(frame, index, qh);
+                }
+            }
+            zwlr_screencopy_frame_v1::Event::BufferDone => state.start_copy(frame, index, qh),
+            zwlr_screencopy_frame_v1::Event::Flags { flags } => {
+                state.frames[index].y_invert = flags
+                    .into_result()
+                    .is_ok_and(|flags| flags.contains(zwlr_screencopy_frame_v1::Flags::YInvert));
+            }
+            zwlr_screencopy_frame_v1::Event::Ready { .. } => {
+                state.frames[index].result = Some(Ok(()));
+            }
+            zwlr_screencopy_frame_v1::Event::Failed => {
+                state.frames[index].result = Some(Err(CaptureError::Failed(
+                    "compositor failed to copy the output".to_string(),
+                )));
+            }
+            _ => {}
+        }
+    }
+}
+
+delegate_noop!(State: ignore WlShm);
+delegate_noop!(State: ignore WlShmPool);
+delegate_noop!(State: ignore WlBuffer);
+delegate_noop!(State: ZwlrScreencopyManagerV1);
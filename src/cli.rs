@@ -0,0 +1,271 @@
+//! Command line parsing.
+//!
+//! Options are registered with GApplication, so they get parsed in the
+//! invoking process and forwarded to an already running instance. The
+//! capture mode is a positional subcommand.
+
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+
+use gio::prelude::*;
+
+use crate::editing_area::{Selection, SelectionConstraint};
+use crate::export::{ExportFormat, ExportOptions, DEFAULT_QUALITY};
+
+const PARAMETER_STRING: &str = "[region | output NAME | window QUERY | full | edit FILE]";
+
+const SUMMARY: &str = "\
+Capture modes:
+  region        Select a region of the screen interactively (default)
+  output NAME   Capture a single monitor, by connector name (e.g. DP-1)
+  window QUERY  Capture a single window, by identifier, app id or title
+  full          Capture every monitor
+  edit FILE     Annotate an existing image
+
+Geometries are in slurp's \"x,y wxh\" format, in the compositor's global
+coordinates, or relative to the image for window and edit.
+
+Without --output, --clipboard or --print-geometry the result is saved to the
+pictures directory.";
+
+/// What to take a screenshot of.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Mode {
+    Region,
+    Output(String),
+    Window(String),
+    Full,
+    Edit(PathBuf),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CliOptions {
+    pub mode: Mode,
+    /// Where to save the result, already resolved against the invoking
+    /// process' working directory.
+    pub output: Option<PathBuf>,
+    pub clipboard: bool,
+    /// Seconds to wait before capturing.
+    pub delay: f64,
+    /// Skip the editor and go straight to saving.
+    pub no_edit: bool,
+    /// Region to start out selected, in global coordinates.
+    pub geometry: Option<Selection>,
+    /// Print the final selection to stdout, like slurp.
+    pub print_geometry: bool,
+    /// Regions covered with black boxes from the start, in global coordinates.
+    pub redact: Vec<Selection>,
+    /// Ratio or size the selection is kept at.
+    pub constraint: SelectionConstraint,
+    /// Image pixels per logical pixel in the result, 0 for the highest
+    /// scale of the captured outputs.
+    pub scale: f64,
+    pub export: ExportOptions,
+}
+
+/// Registers the options with the application, must be called before it runs.
+pub fn register_options(app: &impl IsA<gio::Application>) {
+    app.set_option_context_parameter_string(Some(PARAMETER_STRING));
+    app.set_option_context_summary(Some(SUMMARY));
+
+    app.add_main_option(
+        "output",
+        glib::Char::from(b'o'),
+        glib::OptionFlags::NONE,
+        glib::OptionArg::Filename,
+        "Save the result to PATH",
+        Some("PATH"),
+    );
+    app.add_main_option(
+        "clipboard",
+        glib::Char::from(b'c'),
+        glib::OptionFlags::NONE,
+        glib::OptionArg::None,
+        "Copy the result to the clipboard",
+        None,
+    );
+    app.add_main_option(
+        "delay",
+        glib::Char::from(b'd'),
+        glib::OptionFlags::NONE,
+        glib::OptionArg::Double,
+        "Wait SECONDS before capturing",
+        Some("SECONDS"),
+    );
+    app.add_main_option(
+        "no-edit",
+        glib::Char::from(b'n'),
+        glib::OptionFlags::NONE,
+        glib::OptionArg::None,
+        "Do not open the editor, save the capture right away",
+        None,
+    );
+    app.add_main_option(
+        "geometry",
+        glib::Char::from(b'g'),
+        glib::OptionFlags::NONE,
+        glib::OptionArg::String,
+        "Start with GEOMETRY selected, or capture it right away with --no-edit",
+        Some("GEOMETRY"),
+    );
+    app.add_main_option(
+        "print-geometry",
+        glib::Char::from(b'p'),
+        glib::OptionFlags::NONE,
+        glib::OptionArg::None,
+        "Print the selected geometry to stdout",
+        None,
+    );
+    app.add_main_option(
+        "redact",
+        glib::Char::from(b'x'),
+        glib::OptionFlags::NONE,
+        glib::OptionArg::StringArray,
+        "Cover GEOMETRY with a black box, can be given more than once",
+        Some("GEOMETRY"),
+    );
+    app.add_main_option(
+        "constraint",
+        glib::Char::from(b'r'),
+        glib::OptionFlags::NONE,
+        glib::OptionArg::String,
+        "Keep the selection at a ratio like 16:9 or a size like 1280x720",
+        Some("CONSTRAINT"),
+    );
+    app.add_main_option(
+        "scale",
+        glib::Char::from(b's'),
+        glib::OptionFlags::NONE,
+        glib::OptionArg::Double,
+        "Save at SCALE image pixels per logical pixel, the highest output scale by default",
+        Some("SCALE"),
+    );
+    app.add_main_option(
+        "format",
+        glib::Char::from(b'f'),
+        glib::OptionFlags::NONE,
+        glib::OptionArg::String,
+        "Image format (png, jpeg, webp, webp-lossy, qoi), guessed from --output otherwise",
+        Some("FORMAT"),
+    );
+    app.add_main_option(
+        "quality",
+        glib::Char::from(b'q'),
+        glib::OptionFlags::NONE,
+        glib::OptionArg::Int,
+        "Quality of lossy formats, from 1 to 100",
+        Some("QUALITY"),
+    );
+}
+
+/// Reads the options and subcommand GApplication handed us.
+pub fn parse(command_line: &gio::ApplicationCommandLine) -> Result<CliOptions, String> {
+    let options = command_line.options_dict();
+    let cwd = command_line.cwd();
+    let lookup_error = |error: glib::variant::VariantTypeMismatchError| error.to_string();
+
+    let output = options
+        .lookup::<PathBuf>("output")
+        .map_err(lookup_error)?
+        .map(|path| resolve(&path, cwd.as_deref()));
+    let clipboard = options.contains("clipboard");
+    let no_edit = options.contains("no-edit");
+    let print_geometry = options.contains("print-geometry");
+    let redact = options
+        .lookup::<Vec<String>>("redact")
+        .map_err(lookup_error)?
+        .unwrap_or_default()
+        .iter()
+        .map(|geometry| geometry.parse::<Selection>().map_err(|error| error.to_string()))
+        .collect::<Result<Vec<_>, _>>()?;
+    let geometry = match options.lookup::<String>("geometry").map_err(lookup_error)? {
+        Some(geometry) => Some(geometry.parse::<Selection>().map_err(|error| error.to_string())?),
+        None => None,
+    };
+
+    let delay = options.lookup::<f64>("delay").map_err(lookup_error)?.unwrap_or(0.0);
+    if !delay.is_finite() || delay < 0.0 {
+        return Err(format!("invalid delay {}", delay));
+    }
+
+    let constraint = match options.lookup::<String>("constraint").map_err(lookup_error)? {
+        Some(constraint) => constraint
+            .parse::<SelectionConstraint>()
+            .map_err(|error| error.to_string())?,
+        None => SelectionConstraint::Free,
+    };
+
+    let scale = options.lookup::<f64>("scale").map_err(lookup_error)?.unwrap_or(0.0);
+    if !scale.is_finite() || scale < 0.0 {
+        return Err(format!("invalid scale {}", scale));
+    }
+
+    let format = match options.lookup::<String>("format").map_err(lookup_error)? {
+        Some(name) => Some(
+            ExportFormat::from_name(&name).ok_or_else(|| format!("unknown image format {:?}", name))?,
+        ),
+        None => None,
+    };
+    let quality = match options.lookup::<i32>("quality").map_err(lookup_error)? {
+        Some(quality @ 1..=100) => quality as u8,
+        Some(quality) => return Err(format!("quality must be between 1 and 100, not {}", quality)),
+        None => DEFAULT_QUALITY,
+    };
+
+    // GApplication removed the options it parsed, what is left after the
+    // program name is the subcommand and its argument.
+    let arguments = command_line.arguments();
+    let mode = parse_mode(arguments.get(1..).unwrap_or_default(), cwd.as_deref())?;
+
+    if no_edit && mode == Mode::Region && geometry.is_none() {
+        return Err("region needs an interactive selection or --geometry with --no-edit".to_string());
+    }
+
+    Ok(CliOptions {
+        mode,
+        output,
+        clipboard,
+        delay,
+        no_edit,
+        geometry,
+        print_geometry,
+        redact,
+        constraint,
+        scale,
+        export: ExportOptions { format, quality },
+    })
+}
+
+fn parse_mode(arguments: &[OsString], cwd: Option<&Path>) -> Result<Mode, String> {
+    let mut arguments = arguments.iter();
+    let subcommand = arguments.next().map(|argument| argument.to_string_lossy());
+    let mut operand = |what: &str| {
+        arguments
+            .next()
+            .cloned()
+            .ok_or_else(|| format!("{} is missing", what))
+    };
+
+    let mode = match subcommand.as_deref() {
+        None | Some("region") => Mode::Region,
+        Some("output") => Mode::Output(operand("output name")?.to_string_lossy().into_owned()),
+        Some("window") => Mode::Window(operand("window query")?.to_string_lossy().into_owned()),
+        Some("full") => Mode::Full,
+        Some("edit") => Mode::Edit(resolve(Path::new(&operand("file to edit")?), cwd)),
+        Some(other) => return Err(format!("unknown capture mode {:?}", other)),
+    };
+
+    if let Some(extra) = arguments.next() {
+        return Err(format!("unexpected argument {:?}", extra));
+    }
+    Ok(mode)
+}
+
+/// Makes `path` absolute, relative to the invoking process and not to us,
+/// since we may be an instance started from somewhere else.
+fn resolve(path: &Path, cwd: Option<&Path>) -> PathBuf {
+    match cwd {
+        Some(cwd) if path.is_relative() => cwd.join(path),
+        _ => path.to_path_buf(),
+    }
+}
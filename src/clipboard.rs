@@ -0,0 +1,208 @@
+//! Putting the final image on the Wayland clipboard.
+//!
+//! While windshot runs, the clipboard is served by GDK. Wayland clipboard
+//! contents live only as long as the client offering them, so when we
+//! exit while still owning the clipboard, a detached copy of ourselves
+//! takes over serving the image through wlr-data-control, like wl-copy.
+
+use std::fmt;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::os::unix::process::CommandExt;
+use std::process::{Command, Stdio};
+
+use wayland_client::globals::{registry_queue_init, GlobalListContents};
+use wayland_client::protocol::wl_registry::WlRegistry;
+use wayland_client::protocol::wl_seat::WlSeat;
+use wayland_client::{delegate_noop, event_created_child, Connection, Dispatch, Proxy, QueueHandle};
+use wayland_protocols_wlr::data_control::v1::client::zwlr_data_control_device_v1::{
+    self, ZwlrDataControlDeviceV1,
+};
+use wayland_protocols_wlr::data_control::v1::client::zwlr_data_control_manager_v1::ZwlrDataControlManagerV1;
+use wayland_protocols_wlr::data_control::v1::client::zwlr_data_control_offer_v1::ZwlrDataControlOfferV1;
+use wayland_protocols_wlr::data_control::v1::client::zwlr_data_control_source_v1::{
+    self, ZwlrDataControlSourceV1,
+};
+
+/// Hidden first argument that turns the process into a clipboard server,
+/// reading the PNG to serve from stdin.
+pub const SERVE_CLIPBOARD_ARG: &str = "--serve-clipboard";
+
+const PNG_MIME_TYPE: &str = "image/png";
+
+#[derive(Debug)]
+pub enum ClipboardError {
+    Connect(String),
+    MissingProtocol(&'static str),
+    Io(std::io::Error),
+}
+
+impl fmt::Display for ClipboardError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClipboardError::Connect(error) => {
+                write!(f, "could not connect to the compositor: {}", error)
+            }
+            ClipboardError::MissingProtocol(protocol) => {
+                write!(f, "the compositor does not support {}", protocol)
+            }
+            ClipboardError::Io(error) => write!(f, "I/O error while serving the clipboard: {}", error),
+        }
+    }
+}
+
+impl std::error::Error for ClipboardError {}
+
+impl From<std::io::Error> for ClipboardError {
+    fn from(error: std::io::Error) -> Self {
+        ClipboardError::Io(error)
+    }
+}
+
+impl From<wayland_client::ConnectError> for ClipboardError {
+    fn from(error: wayland_client::ConnectError) -> Self {
+        ClipboardError::Connect(error.to_string())
+    }
+}
+
+impl From<wayland_client::globals::GlobalError> for ClipboardError {
+    fn from(error: wayland_client::globals::GlobalError) -> Self {
+        ClipboardError::Connect(error.to_string())
+    }
+}
+
+impl From<wayland_client::DispatchError> for ClipboardError {
+    fn from(error: wayland_client::DispatchError) -> Self {
+        ClipboardError::Connect(error.to_string())
+    }
+}
+
+/// Offers the PNG on the clipboard through GDK.
+pub fn set_png(clipboard: &gdk4::Clipboard, png: &glib::Bytes) -> Result<(), glib::BoolError> {
+    let provider = gdk4::ContentProvider::for_bytes(PNG_MIME_TYPE, png);
+    clipboard.set_content(Some(&provider))
+}
+
+/// Keeps the PNG on the clipboard after we exit, if it is still ours.
+pub fn persist_if_owned(clipboard: &gdk4::Clipboard, png: &[u8]) {
+    if !clipboard.is_local() {
+        return;
+    }
+    if let Err(error) = spawn_server(png) {
+        eprintln!("Could not keep the image on the clipboard: {}", error);
+    }
+}
+
+/// Starts a detached copy of ourselves serving `png` on the clipboard.
+pub fn spawn_server(png: &[u8]) -> Result<(), ClipboardError> {
+    let mut child = Command::new(std::env::current_exe()?)
+        .arg(SERVE_CLIPBOARD_ARG)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        // Own process group, so the server survives a Ctrl+C in the
+        // terminal that started us.
+        .process_group(0)
+        .spawn()?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(png)?;
+    }
+    Ok(())
+}
+
+struct ServerState {
+    png: Vec<u8>,
+    done: bool,
+}
+
+/// Entry point of the clipboard server: reads a PNG from stdin and serves
+/// it until something else takes over the clipboard.
+pub fn serve_from_stdin() -> Result<(), ClipboardError> {
+    let mut png = Vec::new();
+    std::io::stdin().read_to_end(&mut png)?;
+
+    let connection = Connection::connect_to_env()?;
+    let (globals, mut queue) = registry_queue_init::<ServerState>(&connection)?;
+    let qh = queue.handle();
+
+    let manager = globals
+        .bind::<ZwlrDataControlManagerV1, _, _>(&qh, 1..=2, ())
+        .map_err(|_| ClipboardError::MissingProtocol("zwlr_data_control_manager_v1"))?;
+    let seat = globals
+        .bind::<WlSeat, _, _>(&qh, 1..=1, ())
+        .map_err(|_| ClipboardError::MissingProtocol("wl_seat"))?;
+
+    let device = manager.get_data_device(&seat, &qh, ());
+    let source = manager.create_data_source(&qh, ());
+    source.offer(PNG_MIME_TYPE.to_string());
+    device.set_selection(Some(&source));
+
+    let mut state = ServerState { png, done: false };
+    while !state.done {
+        queue.blocking_dispatch(&mut state)?;
+    }
+
+    source.destroy();
+    device.destroy();
+    manager.destroy();
+    Ok(())
+}
+
+impl Dispatch<WlRegistry, GlobalListContents> for ServerState {
+    fn event(
+        _: &mut Self,
+        _: &WlRegistry,
+        _: <WlRegistry as Proxy>::Event,
+        _: &GlobalListContents,
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+    }
+}
+
+impl Dispatch<ZwlrDataControlSourceV1, ()> for ServerState {
+    fn event(
+        state: &mut Self,
+        _: &ZwlrDataControlSourceV1,
+        event: zwlr_data_control_source_v1::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        match event {
+            zwlr_data_control_source_v1::Event::Send { mime_type, fd } => {
+                if mime_type == PNG_MIME_TYPE {
+                    // The receiving end closing early is not our problem.
+                    let _ = File::from(fd).write_all(&state.png);
+                }
+            }
+            zwlr_data_control_source_v1::Event::Cancelled => state.done = true,
+            _ => {}
+        }
+    }
+}
+
+impl Dispatch<ZwlrDataControlDeviceV1, ()> for ServerState {
+    fn event(
+        state: &mut Self,
+        _: &ZwlrDataControlDeviceV1,
+        event: zwlr_data_control_device_v1::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        match event {
+            zwlr_data_control_device_v1::Event::DataOffer { id } => id.destroy(),
+            zwlr_data_control_device_v1::Event::Finished => state.done = true,
+            _ => {}
+        }
+    }
+
+    event_created_child!(ServerState, ZwlrDataControlDeviceV1, [
+        zwlr_data_control_device_v1::EVT_DATA_OFFER_OPCODE => (ZwlrDataControlOfferV1, ()),
+    ]);
+}
+
+delegate_noop!(ServerState: ignore WlSeat);
+delegate_noop!(ServerState: ignore ZwlrDataControlOfferV1);
+delegate_noop!(ServerState: ZwlrDataControlManagerV1);
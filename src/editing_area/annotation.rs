@@ -0,0 +1,315 @@
+//! Picking commands that were already drawn, to move, resize or delete them.
+
+use glib::object::IsA;
+use gtk4::prelude::WidgetExt;
+
+use super::command::{Command, CommandType};
+use super::rich_text::TextStyle;
+use super::selection::{Selection, SelectionHit};
+
+/// How far from a stroke, on top of half its width, still counts as on it.
+const HIT_TOLERANCE: f32 = 4.0;
+
+/// What dragging a picked command changes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CommandHit {
+    /// A handle or the inside of the command's bounds.
+    Bounds(SelectionHit),
+    /// The point a callout's tail points to, moved on its own.
+    Anchor,
+}
+
+impl CommandHit {
+    pub const fn cursor_name(self) -> &'static str {
+        match self {
+            CommandHit::Bounds(hit) => hit.cursor_name(),
+            CommandHit::Anchor => "grab",
+        }
+    }
+}
+
+/// A command being moved or resized. It only replaces the one on the undo
+/// stack once the drag ends, so the whole drag is a single edit.
+#[derive(Debug, Clone)]
+pub struct CommandEdit {
+    /// Index of the change that added the command on the undo stack.
+    pub index: usize,
+    /// What is being dragged: a handle of the bounds, the interior or a
+    /// callout's anchor.
+    pub hit: CommandHit,
+    /// The command and its bounds as they were when the drag started.
+    pub original: Command,
+    pub original_bounds: Selection,
+    pub bounds: Selection,
+    /// The command as it is now.
+    pub command: Command,
+}
+
+impl CommandEdit {
+    pub fn new(index: usize, hit: CommandHit, command: Command, bounds: graphene::Rect) -> Self {
+        let bounds = Selection::new_from_size((bounds.x(), bounds.y()), bounds.width(), bounds.height());
+        CommandEdit {
+            index,
+            hit,
+            original: command.clone(),
+            original_bounds: bounds,
+            bounds,
+            command,
+        }
+    }
+
+    /// Follows a drag that has moved by `offset` and is now at `point`.
+    pub fn drag_to(&mut self, offset: (f32, f32), point: (f32, f32), widget: impl IsA<gtk4::Widget>) {
+        self.command = self.original.clone();
+        match self.hit {
+            CommandHit::Bounds(SelectionHit::Interior) => {
+                self.bounds = self.original_bounds.translated(offset.0, offset.1);
+                self.command.translate(offset.0, offset.1);
+            }
+            CommandHit::Bounds(SelectionHit::Handle(handle)) => {
+                self.bounds.reshape(handle, point.0, point.1);
+                let handle = handle.after_reshape(&self.bounds, point);
+                self.hit = CommandHit::Bounds(SelectionHit::Handle(handle));
+                self.command.transform(&exact_rect(&self.original_bounds), &exact_rect(&self.bounds));
+            }
+            CommandHit::Anchor => {
+                if let CommandType::Callout { anchor, .. } = &mut self.command.command_type {
+                    *anchor = point;
+                }
+                let bounds = self.command.bounds(widget);
+                self.bounds = Selection::new_from_size((bounds.x(), bounds.y()), bounds.width(), bounds.height());
+            }
+        }
+    }
+}
+
+/// The selection as a rectangle, without rounding it to whole pixels.
+fn exact_rect(selection: &Selection) -> graphene::Rect {
+    let (x, y) = selection.top_left();
+    graphene::Rect::new(x, y, selection.width(), selection.height())
+}
+
+/// Distance from `point` to the segment from `start` to `end`.
+fn segment_distance(point: (f32, f32), start: (f32, f32), end: (f32, f32)) -> f32 {
+    let (dx, dy) = (end.0 - start.0, end.1 - start.1);
+    let length_squared = dx * dx + dy * dy;
+    let t = if length_squared == 0.0 {
+        0.0
+    } else {
+        (((point.0 - start.0) * dx + (point.1 - start.1) * dy) / length_squared).clamp(0.0, 1.0)
+    };
+    (point.0 - start.0 - t * dx).hypot(point.1 - start.1 - t * dy)
+}
+
+/// The rectangle with `start` and `end` as opposite corners.
+fn corners_rect(start: (f32, f32), end: (f32, f32)) -> graphene::Rect {
+    graphene::Rect::new(
+        start.0.min(end.0),
+        start.1.min(end.1),
+        (end.0 - start.0).abs(),
+        (end.1 - start.1).abs(),
+    )
+}
+
+fn contains(rect: &graphene::Rect, point: (f32, f32)) -> bool {
+    point.0 >= rect.x()
+        && point.1 >= rect.y()
+        && point.0 <= rect.x() + rect.width()
+        && point.1 <= rect.y() + rect.height()
+}
+
+impl Command {
+    /// Where a callout's tail points.
+    pub fn anchor(&self) -> Option<(f32, f32)> {
+        match self.command_type {
+            CommandType::Callout { anchor, .. } => Some(anchor),
+            _ => None,
+        }
+    }
+
+    /// The smallest rectangle around the command's geometry, strokes not
+    /// included. Text needs the widget to be laid out.
+    pub fn bounds(&self, widget: impl IsA<gtk4::Widget>) -> graphene::Rect {
+        match &self.command_type {
+            CommandType::Rectangle { end }
+            | CommandType::Circle { end }
+            | CommandType::Line { end }
+            | CommandType::Arrow { end }
+            | CommandType::Blur { end, .. }
+            | CommandType::Pixelate { end, .. }
+            | CommandType::Redact { end } => corners_rect(self.start, *end),
+            CommandType::Freehand { points } => {
+                points.iter().fold(corners_rect(self.start, self.start), |bounds, point| {
+                    bounds.union(&corners_rect(*point, *point))
+                })
+            }
+            CommandType::Text { text, runs, style } => {
+                let layout = Self::text_layout(&widget.pango_context(), text, runs, style);
+                let (_, logical) = layout.pixel_extents();
+                let bounds = graphene::Rect::new(
+                    logical.x() as f32,
+                    logical.y() as f32,
+                    logical.width() as f32,
+                    logical.height() as f32,
+                );
+                // The box may reach past the text.
+                let bounds = match Self::text_box(&layout).filter(|_| style.background.is_some()) {
+                    Some(text_box) => bounds.union(&text_box),
+                    None => bounds,
+                };
+                bounds.offset_r(self.start.0, self.start.1)
+            }
+            CommandType::StepMarker { radius } => corners_rect(
+                (self.start.0 - radius, self.start.1 - radius),
+                (self.start.0 + radius, self.start.1 + radius),
+            ),
+            CommandType::Callout { anchor, text, runs, style } => {
+                let layout = Self::text_layout(&widget.pango_context(), text, runs, style);
+                self.callout_box(&layout).union(&corners_rect(*anchor, *anchor))
+            }
+        }
+    }
+
+    /// Whether `point` is on the command: inside it when it is filled or
+    /// covers an area, near its stroke otherwise.
+    pub fn hit_test(&self, point: (f32, f32), widget: impl IsA<gtk4::Widget>) -> bool {
+        let tolerance = self.width / 2.0 + HIT_TOLERANCE;
+        let near_polyline = |points: &[(f32, f32)]| {
+            points
+                .windows(2)
+                .any(|segment| segment_distance(point, segment[0], segment[1]) <= tolerance)
+        };
+
+        match &self.command_type {
+            CommandType::Rectangle { end } => {
+                let rect = corners_rect(self.start, *end);
+                if self.fill_color.is_some() && contains(&rect, point) {
+                    return true;
+                }
+                let (left, top) = (rect.x(), rect.y());
+                let (right, bottom) = (left + rect.width(), top + rect.height());
+                near_polyline(&[(left, top), (right, top), (right, bottom), (left, bottom), (left, top)])
+            }
+            CommandType::Circle { end } => {
+                let rect = corners_rect(self.start, *end);
+                let (radius_x, radius_y) = (rect.width() / 2.0, rect.height() / 2.0);
+                let (dx, dy) = (
+                    point.0 - rect.x() - radius_x,
+                    point.1 - rect.y() - radius_y,
+                );
+                if radius_x == 0.0 || radius_y == 0.0 {
+                    return near_polyline(&[self.start, *end]);
+                }
+                // How far out the point is, 1 being on the ellipse.
+                let ratio = ((dx / radius_x).powi(2) + (dy / radius_y).powi(2)).sqrt();
+                if self.fill_color.is_some() && ratio <= 1.0 {
+                    return true;
+                }
+                let distance = if ratio == 0.0 {
+                    radius_x.min(radius_y)
+                } else {
+                    (ratio - 1.0).abs() * dx.hypot(dy) / ratio
+                };
+                distance <= tolerance
+            }
+            CommandType::Line { end } | CommandType::Arrow { end } => near_polyline(&[self.start, *end]),
+            CommandType::StepMarker { radius } => {
+                (point.0 - self.start.0).hypot(point.1 - self.start.1) <= *radius
+            }
+            CommandType::Callout { anchor, text, runs, style } => {
+                let layout = Self::text_layout(&widget.pango_context(), text, runs, style);
+                let callout_box = self.callout_box(&layout);
+                let center = (
+                    callout_box.x() + callout_box.width() / 2.0,
+                    callout_box.y() + callout_box.height() / 2.0,
+                );
+                contains(&callout_box, point) || near_polyline(&[center, *anchor])
+            }
+            CommandType::Freehand { points } => {
+                let mut polyline = vec![self.start];
+                polyline.extend_from_slice(points);
+                near_polyline(&polyline)
+            }
+            CommandType::Text { .. }
+            | CommandType::Blur { .. }
+            | CommandType::Pixelate { .. }
+            | CommandType::Redact { .. } => contains(&self.bounds(widget), point),
+        }
+    }
+
+    /// Stretches the command so what was inside `from` fills `to`. Text
+    /// is scaled by the change in height.
+    pub fn transform(&mut self, from: &graphene::Rect, to: &graphene::Rect) {
+        // Flat bounds, like a horizontal line's, can only be moved along
+        // that direction.
+        let scale_x = if from.width() > 0.0 { to.width() / from.width() } else { 1.0 };
+        let scale_y = if from.height() > 0.0 { to.height() / from.height() } else { 1.0 };
+        let map = |point: &mut (f32, f32)| {
+            point.0 = to.x() + (point.0 - from.x()) * scale_x;
+            point.1 = to.y() + (point.1 - from.y()) * scale_y;
+        };
+        // Text grows with the height of its bounds.
+        let scale_font = |style: &mut TextStyle| {
+            let mut font_description = pango::FontDescription::from_string(&style.font);
+            let size = font_description.size() as f32 * scale_y;
+            if font_description.is_size_absolute() {
+                font_description.set_absolute_size(size as f64);
+            } else {
+                font_description.set_size(size.round().max(1.0) as i32);
+            }
+            style.font = font_description.to_string();
+        };
+
+        map(&mut self.start);
+        match &mut self.command_type {
+            CommandType::Rectangle { end }
+            | CommandType::Circle { end }
+            | CommandType::Line { end }
+            | CommandType::Arrow { end }
+            | CommandType::Blur { end, .. }
+            | CommandType::Pixelate { end, .. }
+            | CommandType::Redact { end } => map(end),
+            CommandType::Freehand { points } => points.iter_mut().for_each(map),
+            // Stays round, fitting in the new bounds.
+            CommandType::StepMarker { radius } => *radius *= scale_x.min(scale_y),
+            CommandType::Text { style, .. } => scale_font(style),
+            CommandType::Callout { anchor, style, .. } => {
+                map(anchor);
+                scale_font(style);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn transforming_a_callout_moves_its_anchor_and_scales_its_font() {
+        let mut callout = Command {
+            command_type: CommandType::Callout {
+                anchor: (0.0, 0.0),
+                text: "Here".to_string(),
+                runs: Vec::new(),
+                style: TextStyle::default(),
+            },
+            start: (10.0, 10.0),
+            color: 0xFF0000FF,
+            width: 4.0,
+            fill_color: None,
+        };
+        // Moved by (40, 20) and twice as tall.
+        let from = graphene::Rect::new(10.0, 10.0, 100.0, 20.0);
+        let to = graphene::Rect::new(50.0, 30.0, 100.0, 40.0);
+        callout.transform(&from, &to);
+
+        assert_eq!(callout.start, (50.0, 30.0));
+        let CommandType::Callout { anchor, style, .. } = &callout.command_type else {
+            panic!("the callout changed type");
+        };
+        assert_eq!(*anchor, (40.0, 10.0));
+        let font_description = pango::FontDescription::from_string(&style.font);
+        assert_eq!(font_description.size(), 32 * pango::SCALE);
+    }
+}
@@ -4,12 +4,165 @@ use gtk4::{prelude::{SnapshotExt, WidgetExt}, Widget};
 
 use glib::object::IsA;
 
+use super::redact::Redaction;
+use super::rich_text::{self, TextRun, TextStyle};
+use super::selection::Selection;
+
+/// Something a path can be traced into, so the exact same geometry ends up
+/// in the editing area (gsk) and in the exported image (cairo).
+pub(crate) trait PathSink {
+    fn move_to(&self, x: f32, y: f32);
+    fn line_to(&self, x: f32, y: f32);
+    fn cubic_to(&self, x1: f32, y1: f32, x2: f32, y2: f32, x3: f32, y3: f32);
+    fn add_rect(&self, rect: &graphene::Rect);
+    fn close(&self);
+}
+
+impl PathSink for gsk4::PathBuilder {
+    fn move_to(&self, x: f32, y: f32) {
+        self.move_to(x, y);
+    }
+    fn line_to(&self, x: f32, y: f32) {
+        self.line_to(x, y);
+    }
+    fn cubic_to(&self, x1: f32, y1: f32, x2: f32, y2: f32, x3: f32, y3: f32) {
+        self.cubic_to(x1, y1, x2, y2, x3, y3);
+    }
+    fn add_rect(&self, rect: &graphene::Rect) {
+        self.add_rect(rect);
+    }
+    fn close(&self) {
+        self.close();
+    }
+}
+
+impl PathSink for cairo::Context {
+    fn move_to(&self, x: f32, y: f32) {
+        self.move_to(x as f64, y as f64);
+    }
+    fn line_to(&self, x: f32, y: f32) {
+        self.line_to(x as f64, y as f64);
+    }
+    fn cubic_to(&self, x1: f32, y1: f32, x2: f32, y2: f32, x3: f32, y3: f32) {
+        self.curve_to(x1 as f64, y1 as f64, x2 as f64, y2 as f64, x3 as f64, y3 as f64);
+    }
+    fn add_rect(&self, rect: &graphene::Rect) {
+        self.rectangle(rect.x() as f64, rect.y() as f64, rect.width() as f64, rect.height() as f64);
+    }
+    fn close(&self) {
+        self.close_path();
+    }
+}
+
+/// How far along the tangent a cubic Bézier's control points go, as a
+/// fraction of the radius, to approximate a quarter circle: 4/3·(√2−1).
+pub(crate) const KAPPA: f32 = (4.0 / 3.0) * (SQRT_2 - 1.0);
+
+/// Splits an RGBA color into its channels as floats from 0 to 1.
+pub(crate) const fn color_components(color: u32) -> (f32, f32, f32, f32) {
+    let red: f32 = (((color >> 24) & 0xFF) as f32) / 255.0;
+    let green: f32 = (((color >> 16) & 0xFF) as f32) / 255.0;
+    let blue: f32 = (((color >> 8) & 0xFF) as f32) / 255.0;
+    let alpha: f32 = ((color & 0xFF) as f32) / 255.0;
+    (red, green, blue, alpha)
+}
+
+/// Opaque black or white, whichever is further from `color`'s brightness.
+pub(crate) fn contrasting(color: u32) -> u32 {
+    let (red, green, blue, _) = color_components(color);
+    let luminance = 0.2126 * red + 0.7152 * green + 0.0722 * blue;
+    if luminance > 0.5 {
+        0x000000FF
+    } else {
+        0xFFFFFFFF
+    }
+}
+
+fn rgba(color: u32) -> gdk4::RGBA {
+    let (red, green, blue, alpha) = color_components(color);
+    gdk4::RGBA::new(red, green, blue, alpha)
+}
+
+/// One undoable step on the undo and redo stacks.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Change {
+    /// Draws a new command on top of the others.
+    Add(Command),
+    /// Swaps the command added by the change at `index` of the stack for
+    /// another version of it, or removes it when `None`.
+    Replace { index: usize, command: Option<Command> },
+    /// Moves the command added by the change at `index` to where the one
+    /// added by the change at `to` is, shifting it and those in between
+    /// by one.
+    Move { index: usize, to: usize },
+}
+
+impl Change {
+    /// Moves the commands in the change by (`dx`, `dy`).
+    pub fn translate(&mut self, dx: f32, dy: f32) {
+        match self {
+            Change::Add(command) | Change::Replace { command: Some(command), .. } => {
+                command.translate(dx, dy)
+            }
+            Change::Replace { command: None, .. } | Change::Move { .. } => {}
+        }
+    }
+}
+
 #[derive(Debug, Clone, Default, PartialEq, glib::Boxed)]
 #[boxed_type(name = "CommandsBoxed")]
-pub struct CommandsBoxed(Vec<Command>);
+pub struct CommandsBoxed(Vec<Change>);
+
+impl CommandsBoxed {
+    /// The same changes, moved by (`dx`, `dy`).
+    pub fn translated(&self, dx: f32, dy: f32) -> Self {
+        let mut commands = self.clone();
+        for change in commands.iter_mut() {
+            change.translate(dx, dy);
+        }
+        commands
+    }
+
+    /// The commands as they look after every change, bottom first, each
+    /// with the index of the change that added it.
+    pub fn resolve(&self) -> Vec<(usize, &Command)> {
+        let mut commands: Vec<(usize, Option<&Command>)> = Vec::new();
+        for (index, change) in self.iter().enumerate() {
+            match change {
+                Change::Add(command) => commands.push((index, Some(command))),
+                Change::Replace { index, command } => {
+                    if let Some(slot) = commands.iter_mut().find(|(added, _)| added == index) {
+                        slot.1 = command.as_ref();
+                    }
+                }
+                Change::Move { index, to } => {
+                    let from = commands.iter().position(|(added, _)| added == index);
+                    let to = commands.iter().position(|(added, _)| added == to);
+                    if let (Some(from), Some(to)) = (from, to) {
+                        let slot = commands.remove(from);
+                        commands.insert(to, slot);
+                    }
+                }
+            }
+        }
+        commands
+            .into_iter()
+            .filter_map(|(index, command)| Some((index, command?)))
+            .collect()
+    }
+
+    /// The command added by the change at `index`, as it looks now, if it
+    /// has not been removed.
+    pub fn command(&self, index: usize) -> Option<&Command> {
+        self.resolve()
+            .into_iter()
+            .find(|(added, _)| *added == index)
+            .map(|(_, command)| command)
+    }
+}
 
 impl Deref for CommandsBoxed {
-    type Target = Vec<Command>;
+    type Target = Vec<Change>;
     fn deref(&self) -> &Self::Target {
         &self.0
     }
@@ -21,26 +174,26 @@ impl DerefMut for CommandsBoxed {
     }
 }
 
-impl From<CommandsBoxed> for Vec<Command> {
+impl From<CommandsBoxed> for Vec<Change> {
     fn from(commands: CommandsBoxed) -> Self {
         commands.0
     }
 }
 
-impl From<Vec<Command>> for CommandsBoxed {
-    fn from(commands: Vec<Command>) -> Self {
+impl From<Vec<Change>> for CommandsBoxed {
+    fn from(commands: Vec<Change>) -> Self {
         CommandsBoxed(commands)
     }
 }
 
-impl AsRef<[Command]> for CommandsBoxed {
-    fn as_ref(&self) -> &[Command] {
+impl AsRef<[Change]> for CommandsBoxed {
+    fn as_ref(&self) -> &[Change] {
         &self.0
     }
 }
 
-impl AsMut<[Command]> for CommandsBoxed {
-    fn as_mut(&mut self) -> &mut [Command] {
+impl AsMut<[Change]> for CommandsBoxed {
+    fn as_mut(&mut self) -> &mut [Change] {
         &mut self.0
     }
 }
@@ -61,51 +214,349 @@ pub enum CommandType {
     Circle { end: (f32, f32) },
     Line { end: (f32, f32) },
     Arrow { end: (f32, f32) },
-    Text { font: String, text: String },
+    /// `text` with `start` at the top-left corner of its layout, bold and
+    /// italic where `runs` say.
+    Text { text: String, runs: Vec<TextRun>, style: TextStyle },
     Freehand { points: Vec<(f32, f32)> },
+    /// Blurs what is under the rectangle from `start` to `end`.
+    Blur { end: (f32, f32), radius: f32 },
+    /// Pixelates what is under the rectangle from `start` to `end`.
+    Pixelate { end: (f32, f32), block_size: f32 },
+    /// Covers the rectangle from `start` to `end` in opaque black, and
+    /// destroys what was under it in the exported image.
+    Redact { end: (f32, f32) },
+    /// A disc centred on `start` with its step number in it, given by
+    /// [`number_steps`] when drawing from the marker's place among the
+    /// others.
+    StepMarker { radius: f32 },
+    /// `text` laid out from `start` like a text command, in a rounded box
+    /// sized to it with a tail pointing at `anchor`.
+    Callout {
+        anchor: (f32, f32),
+        text: String,
+        runs: Vec<TextRun>,
+        style: TextStyle,
+    },
+}
+
+/// Numbers the step markers among `commands` 1, 2, 3... in the order they
+/// are drawn, `None` for other commands. Since the numbers follow from the
+/// history, deleting, reordering or undoing markers renumbers the others.
+pub fn number_steps<'a>(commands: impl IntoIterator<Item = &'a Command>) -> Vec<(&'a Command, Option<u32>)> {
+    let mut next = 0;
+    commands
+        .into_iter()
+        .map(|command| match command.command_type {
+            CommandType::StepMarker { .. } => {
+                next += 1;
+                (command, Some(next))
+            }
+            _ => (command, None),
+        })
+        .collect()
 }
 
 impl Command {
-    pub fn draw_to_snapshot(&self, snapshot: &gtk4::Snapshot, widget: impl IsA<Widget>) {
-        let path_builder = gsk4::PathBuilder::new();
+    /// A black box over `selection`.
+    pub fn redact(selection: &Selection) -> Self {
+        Command {
+            command_type: CommandType::Redact { end: selection.bottom_right() },
+            start: selection.top_left(),
+            color: 0x000000FF,
+            width: 0.0,
+            fill_color: None,
+        }
+    }
 
-        snapshot.save();
+    /// Whether this is a black box. Those are applied to the capture before
+    /// anything else is drawn, so no other command can spread what they
+    /// hide, like a blur stacked before them would.
+    pub const fn is_redact_box(&self) -> bool {
+        matches!(self.command_type, CommandType::Redact { .. })
+    }
+
+    /// Moves the whole command by (`dx`, `dy`).
+    pub fn translate(&mut self, dx: f32, dy: f32) {
+        let offset = |point: &mut (f32, f32)| {
+            point.0 += dx;
+            point.1 += dy;
+        };
+        offset(&mut self.start);
+        match &mut self.command_type {
+            CommandType::Rectangle { end }
+            | CommandType::Circle { end }
+            | CommandType::Line { end }
+            | CommandType::Arrow { end }
+            | CommandType::Blur { end, .. }
+            | CommandType::Pixelate { end, .. }
+            | CommandType::Redact { end } => offset(end),
+            CommandType::Freehand { points } => points.iter_mut().for_each(offset),
+            CommandType::Callout { anchor, .. } => offset(anchor),
+            CommandType::Text { .. } | CommandType::StepMarker { .. } => {}
+        }
+    }
+
+    /// Whether the command has text that can be typed in.
+    pub const fn has_text(&self) -> bool {
+        matches!(self.command_type, CommandType::Text { .. } | CommandType::Callout { .. })
+    }
+
+    /// How the command hides what is under it, and where, if it is a
+    /// redaction rather than a shape.
+    pub fn redaction(&self) -> Option<(Redaction, graphene::Rect)> {
+        let (redaction, end) = match self.command_type {
+            CommandType::Blur { end, radius } => (Redaction::Blur { radius }, end),
+            CommandType::Pixelate { end, block_size } => (Redaction::Pixelate { block_size }, end),
+            CommandType::Redact { end } => (Redaction::Fill, end),
+            _ => return None,
+        };
+        let rect = graphene::Rect::new(
+            self.start.0.min(end.0),
+            self.start.1.min(end.1),
+            (end.0 - self.start.0).abs(),
+            (end.1 - self.start.1).abs(),
+        );
+        Some((redaction, rect))
+    }
+
+    /// Draws the command on top of `below`, everything drawn before it,
+    /// which redactions need to hide. `step` is the number [`number_steps`]
+    /// gives a step marker.
+    pub fn draw_to_snapshot(
+        &self,
+        snapshot: &gtk4::Snapshot,
+        widget: impl IsA<Widget>,
+        below: Option<&gsk4::RenderNode>,
+        step: Option<u32>,
+    ) {
+        let path_builder = gsk4::PathBuilder::new();
+        let mut text_layout = None;
         match &self.command_type {
+            CommandType::Blur { .. } | CommandType::Pixelate { .. } | CommandType::Redact { .. } => {
+                if let Some((redaction, rect)) = self.redaction() {
+                    redaction.draw_to_snapshot(snapshot, widget, below, &rect);
+                }
+                return;
+            }
+            CommandType::StepMarker { radius } => {
+                return self.draw_step_marker_to_snapshot(snapshot, widget, *radius, step);
+            }
+            CommandType::Callout { anchor, text, runs, style } => {
+                return self.draw_callout_to_snapshot(snapshot, widget, *anchor, text, runs, style);
+            }
             CommandType::Rectangle { end } => self.path_rectangle(&path_builder, *end),
             CommandType::Line { end } => self.path_line(&path_builder, *end),
             CommandType::Arrow { end } => self.path_arrow(&path_builder, *end),
             CommandType::Freehand { points } => self.path_freehand(&path_builder, points),
-            CommandType::Text { font , text} => 
-                self.path_text(&path_builder, snapshot, widget, font, text),
+            CommandType::Text { text, runs, style } => {
+                text_layout = Some(self.path_text(&path_builder, widget, text, runs, style))
+            }
             CommandType::Circle { end} => self.path_circle(&path_builder, *end),
         }
 
         let path = path_builder.to_path();
 
+        snapshot.save();
+        if let (Some(layout), CommandType::Text { style, .. }) = (&text_layout, &self.command_type) {
+            // The glyphs are laid out from the origin.
+            snapshot.translate(&graphene::Point::new(self.start.0, self.start.1));
+            self.draw_text_decorations_to_snapshot(snapshot, layout, style, &path);
+        }
         if let Some(fill_color) = self.fill_color {
-            // color is RGBA, get each color as f32
-            let red: f32 = (((fill_color >> 24) & 0xFF) as f32) / 255.0;
-            let green: f32 = (((fill_color >> 16) & 0xFF) as f32) / 255.0;
-            let blue: f32 = (((fill_color >> 8) & 0xFF) as f32) / 255.0;
-            let alpha: f32 = ((fill_color & 0xFF) as f32) / 255.0;
-
+            let (red, green, blue, alpha) = color_components(fill_color);
             let color = gdk4::RGBA::new(red, green, blue, alpha);
             snapshot.append_fill(&path, gsk4::FillRule::Winding, &color);
         }
         let stroke = gsk4::Stroke::new(self.width);
-        // color is RGBA, get each color as f32
-        let red: f32 = (((self.color >> 24) & 0xFF) as f32) / 255.0;
-        let green: f32 = (((self.color >> 16) & 0xFF) as f32) / 255.0;
-        let blue: f32 = (((self.color >> 8) & 0xFF) as f32) / 255.0;
-        let alpha: f32 = ((self.color & 0xFF) as f32) / 255.0;
-
+        let (red, green, blue, alpha) = color_components(self.color);
         let color = gdk4::RGBA::new(red, green, blue, alpha);
         snapshot.append_stroke(&path, &stroke, &color);
 
         snapshot.restore();
     }
 
-    pub(crate) fn path_rectangle(&self, path_builder: &gsk4::PathBuilder, end: (f32, f32)) {
+    /// Draws the command with cairo, the same way [`Self::draw_to_snapshot`]
+    /// does with gsk. Used to render the final image without a display.
+    ///
+    /// Redactions replace the pixels already drawn to `cr`'s target.
+    pub fn draw_to_cairo(&self, cr: &cairo::Context, step: Option<u32>) -> Result<(), cairo::Error> {
+        cr.new_path();
+        let mut text_layout = None;
+        match &self.command_type {
+            CommandType::Blur { .. } | CommandType::Pixelate { .. } | CommandType::Redact { .. } => {
+                return match self.redaction() {
+                    Some((redaction, rect)) => redaction.apply_to_cairo(cr, &rect),
+                    None => Ok(()),
+                };
+            }
+            CommandType::StepMarker { radius } => return self.draw_step_marker_to_cairo(cr, *radius, step),
+            CommandType::Callout { anchor, text, runs, style } => {
+                return self.draw_callout_to_cairo(cr, *anchor, text, runs, style);
+            }
+            CommandType::Rectangle { end } => self.path_rectangle(cr, *end),
+            CommandType::Line { end } => self.path_line(cr, *end),
+            CommandType::Arrow { end } => self.path_arrow(cr, *end),
+            CommandType::Freehand { points } => self.path_freehand(cr, points),
+            CommandType::Text { text, runs, style } => {
+                text_layout = Some(self.path_text_cairo(cr, text, runs, style))
+            }
+            CommandType::Circle { end } => self.path_circle(cr, *end),
+        }
+
+        cr.save()?;
+        if let (Some(layout), CommandType::Text { style, .. }) = (&text_layout, &self.command_type) {
+            self.draw_text_decorations_to_cairo(cr, layout, style)?;
+        }
+
+        self.fill_and_stroke_cairo(cr)?;
+
+        cr.restore()
+    }
+
+    /// Fills `cr`'s current path with the fill color, if any, and strokes
+    /// it, consuming the path.
+    fn fill_and_stroke_cairo(&self, cr: &cairo::Context) -> Result<(), cairo::Error> {
+        if let Some(fill_color) = self.fill_color {
+            let (red, green, blue, alpha) = color_components(fill_color);
+            cr.set_source_rgba(red as f64, green as f64, blue as f64, alpha as f64);
+            cr.fill_preserve()?;
+        }
+        // Match the defaults of gsk4::Stroke.
+        cr.set_line_width(self.width as f64);
+        cr.set_line_cap(cairo::LineCap::Butt);
+        cr.set_line_join(cairo::LineJoin::Miter);
+        cr.set_miter_limit(4.0);
+        let (red, green, blue, alpha) = color_components(self.color);
+        cr.set_source_rgba(red as f64, green as f64, blue as f64, alpha as f64);
+        cr.stroke()?;
+        Ok(())
+    }
+
+    /// Lays out a step marker's number, and where to put the layout for
+    /// the digits' ink to be centred on the marker.
+    pub(crate) fn step_layout(&self, pango_context: &pango::Context, radius: f32, number: u32) -> (pango::Layout, (f32, f32)) {
+        let mut font_description = pango::FontDescription::from_string("Sans Bold");
+        font_description.set_absolute_size(radius as f64 * 1.2 * pango::SCALE as f64);
+        let layout = pango::Layout::new(pango_context);
+        layout.set_font_description(Some(&font_description));
+        layout.set_text(&number.to_string());
+
+        let (ink, _) = layout.extents();
+        let scale = pango::SCALE as f32;
+        let center = (
+            (ink.x() as f32 + ink.width() as f32 / 2.0) / scale,
+            (ink.y() as f32 + ink.height() as f32 / 2.0) / scale,
+        );
+        (layout, (self.start.0 - center.0, self.start.1 - center.1))
+    }
+
+    /// A disc in the command's color, the number on it, if any, in black or
+    /// white.
+    fn draw_step_marker_to_snapshot(
+        &self,
+        snapshot: &gtk4::Snapshot,
+        widget: impl IsA<Widget>,
+        radius: f32,
+        number: Option<u32>,
+    ) {
+        let (x, y) = self.start;
+        let disc = gsk4::PathBuilder::new();
+        path_ellipse(&disc, (x - radius, y - radius), (x + radius, y + radius));
+        snapshot.append_fill(&disc.to_path(), gsk4::FillRule::Winding, &rgba(self.color));
+
+        let Some(number) = number else {
+            return;
+        };
+        let (layout, position) = self.step_layout(&widget.pango_context(), radius, number);
+        let digits = gsk4::PathBuilder::new();
+        digits.add_layout(&layout);
+        snapshot.save();
+        snapshot.translate(&graphene::Point::new(position.0, position.1));
+        snapshot.append_fill(&digits.to_path(), gsk4::FillRule::Winding, &rgba(contrasting(self.color)));
+        snapshot.restore();
+    }
+
+    fn draw_step_marker_to_cairo(&self, cr: &cairo::Context, radius: f32, number: Option<u32>) -> Result<(), cairo::Error> {
+        let (x, y) = self.start;
+        cr.save()?;
+        cr.new_path();
+        path_ellipse(cr, (x - radius, y - radius), (x + radius, y + radius));
+        let (red, green, blue, alpha) = color_components(self.color);
+        cr.set_source_rgba(red as f64, green as f64, blue as f64, alpha as f64);
+        cr.fill()?;
+
+        if let Some(number) = number {
+            let (layout, position) = self.step_layout(&pangocairo::functions::create_context(cr), radius, number);
+            cr.move_to(position.0 as f64, position.1 as f64);
+            pangocairo::functions::layout_path(cr, &layout);
+            let (red, green, blue, alpha) = color_components(contrasting(self.color));
+            cr.set_source_rgba(red as f64, green as f64, blue as f64, alpha as f64);
+            cr.fill()?;
+        }
+        cr.restore()
+    }
+
+    /// The box of a callout whose text is laid out in `layout`: the text's
+    /// logical extents with some room around them.
+    pub(crate) fn callout_box(&self, layout: &pango::Layout) -> graphene::Rect {
+        let (_, logical) = layout.pixel_extents();
+        graphene::Rect::new(
+            self.start.0 + logical.x() as f32 - rich_text::BOX_PADDING,
+            self.start.1 + logical.y() as f32 - rich_text::BOX_PADDING,
+            logical.width() as f32 + 2.0 * rich_text::BOX_PADDING,
+            logical.height() as f32 + 2.0 * rich_text::BOX_PADDING,
+        )
+    }
+
+    /// The box and tail filled with the fill color, if any, and outlined
+    /// like other shapes, then the text in the stroke color.
+    fn draw_callout_to_snapshot(
+        &self,
+        snapshot: &gtk4::Snapshot,
+        widget: impl IsA<Widget>,
+        anchor: (f32, f32),
+        text: &str,
+        runs: &[TextRun],
+        style: &TextStyle,
+    ) {
+        let layout = Self::text_layout(&widget.pango_context(), text, runs, style);
+        let outline = gsk4::PathBuilder::new();
+        path_callout(&outline, &self.callout_box(&layout), rich_text::BOX_RADIUS, Some(anchor));
+        let outline = outline.to_path();
+        if let Some(fill_color) = self.fill_color {
+            snapshot.append_fill(&outline, gsk4::FillRule::Winding, &rgba(fill_color));
+        }
+        snapshot.append_stroke(&outline, &gsk4::Stroke::new(self.width), &rgba(self.color));
+
+        let glyphs = gsk4::PathBuilder::new();
+        glyphs.add_layout(&layout);
+        snapshot.save();
+        snapshot.translate(&graphene::Point::new(self.start.0, self.start.1));
+        snapshot.append_fill(&glyphs.to_path(), gsk4::FillRule::Winding, &rgba(self.color));
+        snapshot.restore();
+    }
+
+    fn draw_callout_to_cairo(
+        &self,
+        cr: &cairo::Context,
+        anchor: (f32, f32),
+        text: &str,
+        runs: &[TextRun],
+        style: &TextStyle,
+    ) -> Result<(), cairo::Error> {
+        cr.save()?;
+        let layout = self.path_text_cairo(cr, text, runs, style);
+        let glyphs = cr.copy_path()?;
+        cr.new_path();
+        path_callout(cr, &self.callout_box(&layout), rich_text::BOX_RADIUS, Some(anchor));
+        self.fill_and_stroke_cairo(cr)?;
+
+        cr.append_path(&glyphs);
+        cr.fill()?;
+        cr.restore()
+    }
+
+    pub(crate) fn path_rectangle(&self, path_builder: &impl PathSink, end: (f32, f32)) {
         let (end_x, end_y) = end;
         let width = end_x - self.start.0;
         let height = end_y - self.start.1;
@@ -114,13 +565,13 @@ impl Command {
         path_builder.add_rect(&rect);
     }
 
-    pub(crate) fn path_line(&self, path_builder: &gsk4::PathBuilder, end: (f32, f32)) {
+    pub(crate) fn path_line(&self, path_builder: &impl PathSink, end: (f32, f32)) {
         let (end_x, end_y) = end;
         path_builder.move_to(self.start.0, self.start.1);
         path_builder.line_to(end_x, end_y);
     }
 
-    pub(crate) fn path_arrow(&self, path_builder: &gsk4::PathBuilder, end: (f32, f32)) {
+    pub(crate) fn path_arrow(&self, path_builder: &impl PathSink, end: (f32, f32)) {
         pub(crate) const HEAD_LENGTH: f32 = 15.0;
         pub(crate) const HEAD_ANGLE: f32 = 90.0;
         let (start_x, start_y) = self.start;
@@ -166,82 +617,379 @@ impl Command {
         path_builder.move_to(end_x, end_y);
     }
 
-    pub(crate) fn path_freehand(&self, path_builder: &gsk4::PathBuilder, points: &[(f32, f32)]) {
+    pub(crate) fn path_freehand(&self, path_builder: &impl PathSink, points: &[(f32, f32)]) {
         path_builder.move_to(self.start.0, self.start.1);
         for (x, y) in points {
             path_builder.line_to(*x, *y);
         }
     }
 
-    pub(crate) fn path_text(&self, path_builder: &gsk4::PathBuilder, snapshot: &gtk4::Snapshot, widget: impl IsA<Widget>, font: &str, text: &str) {
+    pub(crate) fn path_text(
+        &self,
+        path_builder: &gsk4::PathBuilder,
+        widget: impl IsA<Widget>,
+        text: &str,
+        runs: &[TextRun],
+        style: &TextStyle,
+    ) -> pango::Layout {
         let pango_context = widget.pango_context();
-        let font_description = pango::FontDescription::from_string(font);
+        let layout = Self::text_layout(&pango_context, text, runs, style);
+
+        path_builder.add_layout(&layout);
+        layout
+    }
+
+    pub(crate) fn path_text_cairo(
+        &self,
+        cr: &cairo::Context,
+        text: &str,
+        runs: &[TextRun],
+        style: &TextStyle,
+    ) -> pango::Layout {
+        let pango_context = pangocairo::functions::create_context(cr);
+        let layout = Self::text_layout(&pango_context, text, runs, style);
+
+        cr.move_to(self.start.0 as f64, self.start.1 as f64);
+        pangocairo::functions::layout_path(cr, &layout);
+        layout
+    }
+
+    /// Lays out the text of a text command, shared between the gsk and
+    /// the cairo paths so both end up with the same glyphs.
+    pub(crate) fn text_layout(
+        pango_context: &pango::Context,
+        text: &str,
+        runs: &[TextRun],
+        style: &TextStyle,
+    ) -> pango::Layout {
+        let font_description = pango::FontDescription::from_string(&style.font);
         let lang = pango::Language::default();
         pango_context.load_fontset(&font_description, &lang);
 
-        let layout = pango::Layout::new(&pango_context);
-        layout.set_text(text);
+        let layout = pango::Layout::new(pango_context);
+        layout.set_font_description(Some(&font_description));
+        layout.set_alignment(style.alignment);
+        layout.set_line_spacing(style.line_spacing);
+        if runs.is_empty() {
+            layout.set_text(text);
+        } else {
+            layout.set_markup(&rich_text::markup(text, runs));
+        }
+        layout
+    }
 
-        path_builder.add_layout(&layout);
-        let translate_point = graphene::Point::new(self.start.0, self.start.1);
-        snapshot.translate(&translate_point);
+    /// The rounded box drawn under a text, around its ink, relative to
+    /// where the text starts. `None` when there is no ink.
+    pub(crate) fn text_box(layout: &pango::Layout) -> Option<graphene::Rect> {
+        let (ink, _) = layout.pixel_extents();
+        if ink.width() == 0 || ink.height() == 0 {
+            return None;
+        }
+        Some(graphene::Rect::new(
+            ink.x() as f32 - rich_text::BOX_PADDING,
+            ink.y() as f32 - rich_text::BOX_PADDING,
+            ink.width() as f32 + 2.0 * rich_text::BOX_PADDING,
+            ink.height() as f32 + 2.0 * rich_text::BOX_PADDING,
+        ))
     }
 
-    pub(crate) fn path_circle(&self, path_builder: &gsk4::PathBuilder, end: (f32, f32)) {
-        let (end_x, end_y) = end;
+    /// Draws the background box and the halo under the glyphs in `path`,
+    /// with the snapshot already at the start of the text.
+    fn draw_text_decorations_to_snapshot(
+        &self,
+        snapshot: &gtk4::Snapshot,
+        layout: &pango::Layout,
+        style: &TextStyle,
+        path: &gsk4::Path,
+    ) {
+        if let (Some(background), Some(rect)) = (style.background, Self::text_box(layout)) {
+            let path_builder = gsk4::PathBuilder::new();
+            path_rounded_rect(&path_builder, &rect, rich_text::BOX_RADIUS);
+            let (red, green, blue, alpha) = color_components(background);
+            let color = gdk4::RGBA::new(red, green, blue, alpha);
+            snapshot.append_fill(&path_builder.to_path(), gsk4::FillRule::Winding, &color);
+        }
+        if let Some(halo) = style.halo {
+            let stroke = gsk4::Stroke::new(self.width + 2.0 * rich_text::HALO_WIDTH);
+            stroke.set_line_join(gsk4::LineJoin::Round);
+            let (red, green, blue, alpha) = color_components(halo);
+            let color = gdk4::RGBA::new(red, green, blue, alpha);
+            snapshot.append_stroke(path, &stroke, &color);
+        }
+    }
 
-        let start_x = self.start.0;
-        let start_y = self.start.1;
-
-        let left = start_x.min(end_x);
-        let right = start_x.max(end_x);
-        let top = start_y.min(end_y);
-        let bottom = start_y.max(end_y);
-
-        let center_x = (left + right) / 2.0;
-        let center_y = (top + bottom) / 2.0;
-        let radius_x: f32 = (right - left) / 2.0;
-        let radius_y: f32 = (bottom - top) / 2.0;
-
-
-        // Magic number gemini told me
-        pub(crate) const KAPPA: f32 = (4.0 / 3.0) * (SQRT_2 - 1.0);
-
-        let control_dx = radius_x * KAPPA;
-        let control_dy = radius_y * KAPPA;
-    
-        path_builder.move_to(right, center_y);
-
-        // I need to use cubic beziers instead of conics
-        // because when this will eventually be converted
-        // to a cairo path, conics are not supported
-        // by cairo and will approximate them with cubic
-        // beziers. So it is better the user sees
-        // the approximation in the editing area,
-        // rather than seeing perfect conics and
-        // then seeing them approximated in the final
-        // image.
-        path_builder.cubic_to(
-            right, center_y - control_dy,
-            center_x + control_dx, top,
-            center_x, top,
-        );
-        path_builder.cubic_to(
-            center_x - control_dx, top,
-            left, center_y - control_dy,
-            left, center_y,
-        );
-        path_builder.cubic_to(
-            left, center_y + control_dy,
-            center_x - control_dx, bottom,
-            center_x, bottom,
-        );
-        path_builder.cubic_to(
-            center_x + control_dx, bottom,
-            right, center_y + control_dy,
-            right, center_y,
-        );
+    /// Draws the background box and the halo under the glyphs in `cr`'s
+    /// current path, which is kept.
+    fn draw_text_decorations_to_cairo(
+        &self,
+        cr: &cairo::Context,
+        layout: &pango::Layout,
+        style: &TextStyle,
+    ) -> Result<(), cairo::Error> {
+        let glyphs = cr.copy_path()?;
+        if let (Some(background), Some(rect)) = (style.background, Self::text_box(layout)) {
+            let rect = graphene::Rect::new(rect.x() + self.start.0, rect.y() + self.start.1, rect.width(), rect.height());
+            cr.new_path();
+            path_rounded_rect(cr, &rect, rich_text::BOX_RADIUS);
+            let (red, green, blue, alpha) = color_components(background);
+            cr.set_source_rgba(red as f64, green as f64, blue as f64, alpha as f64);
+            cr.fill()?;
+        }
+        if let Some(halo) = style.halo {
+            cr.new_path();
+            cr.append_path(&glyphs);
+            cr.set_line_width((self.width + 2.0 * rich_text::HALO_WIDTH) as f64);
+            cr.set_line_join(cairo::LineJoin::Round);
+            let (red, green, blue, alpha) = color_components(halo);
+            cr.set_source_rgba(red as f64, green as f64, blue as f64, alpha as f64);
+            cr.stroke()?;
+        }
+        cr.new_path();
+        cr.append_path(&glyphs);
+        Ok(())
+    }
+
+    pub(crate) fn path_circle(&self, path_builder: &impl PathSink, end: (f32, f32)) {
+        path_ellipse(path_builder, self.start, end);
+    }
+}
+
+/// Traces the ellipse inside the rectangle with `start` and `end` as
+/// opposite corners.
+pub(crate) fn path_ellipse(path_builder: &impl PathSink, start: (f32, f32), end: (f32, f32)) {
+    let (start_x, start_y) = start;
+    let (end_x, end_y) = end;
+
+    let left = start_x.min(end_x);
+    let right = start_x.max(end_x);
+    let top = start_y.min(end_y);
+    let bottom = start_y.max(end_y);
+
+    let center_x = (left + right) / 2.0;
+    let center_y = (top + bottom) / 2.0;
+    let radius_x: f32 = (right - left) / 2.0;
+    let radius_y: f32 = (bottom - top) / 2.0;
+
+    let control_dx = radius_x * KAPPA;
+    let control_dy = radius_y * KAPPA;
+
+    path_builder.move_to(right, center_y);
+
+    // I need to use cubic beziers instead of conics
+    // because when this will eventually be converted
+    // to a cairo path, conics are not supported
+    // by cairo and will approximate them with cubic
+    // beziers. So it is better the user sees
+    // the approximation in the editing area,
+    // rather than seeing perfect conics and
+    // then seeing them approximated in the final
+    // image.
+    path_builder.cubic_to(
+        right, center_y - control_dy,
+        center_x + control_dx, top,
+        center_x, top,
+    );
+    path_builder.cubic_to(
+        center_x - control_dx, top,
+        left, center_y - control_dy,
+        left, center_y,
+    );
+    path_builder.cubic_to(
+        left, center_y + control_dy,
+        center_x - control_dx, bottom,
+        center_x, bottom,
+    );
+    path_builder.cubic_to(
+        center_x + control_dx, bottom,
+        right, center_y + control_dy,
+        right, center_y,
+    );
+
+    path_builder.close();
+}
+
+/// Traces `rect` with corners rounded by `radius`, or less if it is too
+/// small for that.
+pub(crate) fn path_rounded_rect(path_builder: &impl PathSink, rect: &graphene::Rect, radius: f32) {
+    path_callout(path_builder, rect, radius, None);
+}
 
-        path_builder.close();
+/// Half the width of a callout's tail where it leaves the box.
+const TAIL_HALF_WIDTH: f32 = 8.0;
+
+/// Traces a rounded rectangle like [`path_rounded_rect`], with a tail from
+/// the side facing `anchor` to it, unless it is inside.
+pub(crate) fn path_callout(
+    path_builder: &impl PathSink,
+    rect: &graphene::Rect,
+    radius: f32,
+    anchor: Option<(f32, f32)>,
+) {
+    let (left, top) = (rect.x(), rect.y());
+    let (right, bottom) = (left + rect.width(), top + rect.height());
+    let radius = radius.min(rect.width() / 2.0).min(rect.height() / 2.0);
+    let control = radius * (1.0 - KAPPA);
+
+    // Which side the tail leaves from, the one the anchor is furthest out
+    // of relative to the box's size.
+    #[derive(PartialEq)]
+    enum Side {
+        Top,
+        Right,
+        Bottom,
+        Left,
+    }
+    let anchor = anchor.filter(|anchor| !rect.contains_point(&graphene::Point::new(anchor.0, anchor.1)));
+    let side = anchor.map(|(x, y)| {
+        let dx = (x - (left + right) / 2.0) / rect.width().max(1.0);
+        let dy = (y - (top + bottom) / 2.0) / rect.height().max(1.0);
+        match (dx.abs() > dy.abs(), dx < 0.0, dy < 0.0) {
+            (true, true, _) => Side::Left,
+            (true, false, _) => Side::Right,
+            (false, _, true) => Side::Top,
+            (false, _, false) => Side::Bottom,
+        }
+    });
+    let (anchor_x, anchor_y) = anchor.unwrap_or_default();
+    // Where the tail's base starts and ends along a side from `from` to
+    // `to`, as close to facing the anchor as the corners allow.
+    let base = |from: f32, to: f32, toward: f32| {
+        let half = TAIL_HALF_WIDTH.min((to - from) / 2.0 - radius).max(0.0);
+        let center = toward.clamp(from + radius + half, to - radius - half);
+        (center - half, center + half)
+    };
+
+    path_builder.move_to(left + radius, top);
+    if side == Some(Side::Top) {
+        let (start, end) = base(left, right, anchor_x);
+        path_builder.line_to(start, top);
+        path_builder.line_to(anchor_x, anchor_y);
+        path_builder.line_to(end, top);
+    }
+    path_builder.line_to(right - radius, top);
+    path_builder.cubic_to(right - control, top, right, top + control, right, top + radius);
+    if side == Some(Side::Right) {
+        let (start, end) = base(top, bottom, anchor_y);
+        path_builder.line_to(right, start);
+        path_builder.line_to(anchor_x, anchor_y);
+        path_builder.line_to(right, end);
+    }
+    path_builder.line_to(right, bottom - radius);
+    path_builder.cubic_to(right, bottom - control, right - control, bottom, right - radius, bottom);
+    if side == Some(Side::Bottom) {
+        let (start, end) = base(left, right, anchor_x);
+        path_builder.line_to(end, bottom);
+        path_builder.line_to(anchor_x, anchor_y);
+        path_builder.line_to(start, bottom);
+    }
+    path_builder.line_to(left + radius, bottom);
+    path_builder.cubic_to(left + control, bottom, left, bottom - control, left, bottom - radius);
+    if side == Some(Side::Left) {
+        let (start, end) = base(top, bottom, anchor_y);
+        path_builder.line_to(left, end);
+        path_builder.line_to(anchor_x, anchor_y);
+        path_builder.line_to(left, start);
+    }
+    path_builder.line_to(left, top + radius);
+    path_builder.cubic_to(left, top + control, left + control, top, left + radius, top);
+    path_builder.close();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A step marker told apart from the others by its `x`.
+    fn marker(x: f32) -> Command {
+        Command {
+            command_type: CommandType::StepMarker { radius: 14.0 },
+            start: (x, 0.0),
+            color: 0xFF0000FF,
+            width: 4.0,
+            fill_color: None,
+        }
+    }
+
+    fn rectangle() -> Command {
+        Command {
+            command_type: CommandType::Rectangle { end: (10.0, 10.0) },
+            start: (0.0, 0.0),
+            color: 0xFF0000FF,
+            width: 4.0,
+            fill_color: None,
+        }
+    }
+
+    /// The markers left after `changes` as (x, number), in drawing order.
+    fn steps(changes: &[Change]) -> Vec<(f32, u32)> {
+        let commands = CommandsBoxed::from(changes.to_vec());
+        let resolved = commands.resolve();
+        number_steps(resolved.iter().map(|(_, command)| *command))
+            .into_iter()
+            .filter_map(|(command, step)| Some((command.start.0, step?)))
+            .collect()
+    }
+
+    /// Three markers with a rectangle between the first two, added by the
+    /// changes at 0, 2 and 3.
+    fn added() -> Vec<Change> {
+        vec![
+            Change::Add(marker(1.0)),
+            Change::Add(rectangle()),
+            Change::Add(marker(2.0)),
+            Change::Add(marker(3.0)),
+        ]
+    }
+
+    #[test]
+    fn markers_are_numbered_in_drawing_order() {
+        assert_eq!(steps(&added()), [(1.0, 1), (2.0, 2), (3.0, 3)]);
+        let numbers: Vec<_> = number_steps([&marker(1.0), &rectangle()]).into_iter().map(|(_, step)| step).collect();
+        assert_eq!(numbers, [Some(1), None]);
+    }
+
+    #[test]
+    fn deleting_a_marker_renumbers_the_later_ones() {
+        let mut changes = added();
+        changes.push(Change::Replace { index: 2, command: None });
+        assert_eq!(steps(&changes), [(1.0, 1), (3.0, 2)]);
+    }
+
+    #[test]
+    fn moving_a_marker_renumbers_the_ones_it_passes() {
+        let mut changes = added();
+        changes.push(Change::Move { index: 3, to: 0 });
+        assert_eq!(steps(&changes), [(3.0, 1), (1.0, 2), (2.0, 3)]);
+        changes.push(Change::Move { index: 3, to: 2 });
+        assert_eq!(steps(&changes), [(1.0, 1), (2.0, 2), (3.0, 3)]);
+    }
+
+    #[test]
+    fn undoing_restores_the_numbers() {
+        // Undoing pops the last change off the stack.
+        let mut changes = added();
+        changes.push(Change::Replace { index: 0, command: None });
+        assert_eq!(steps(&changes), [(2.0, 1), (3.0, 2)]);
+        changes.pop();
+        assert_eq!(steps(&changes), [(1.0, 1), (2.0, 2), (3.0, 3)]);
+        changes.pop();
+        assert_eq!(steps(&changes), [(1.0, 1), (2.0, 2)]);
+    }
+
+    #[test]
+    fn a_marker_without_a_number_is_a_plain_disc() -> Result<(), cairo::Error> {
+        let mut surface = cairo::ImageSurface::create(cairo::Format::ARgb32, 40, 20)?;
+        let cr = cairo::Context::new(&surface)?;
+        marker(20.0).draw_to_cairo(&cr, None)?;
+        drop(cr);
+
+        // The row through the middle, where the digits would be.
+        let stride = surface.stride() as usize;
+        let data = surface.data().expect("the context is gone");
+        let row = &data[2 * stride..][..40 * 4];
+        for x in 10..30 {
+            assert_eq!(row[x * 4..][..4], 0xFFFF0000u32.to_ne_bytes(), "pixel {}", x);
+        }
+        Ok(())
     }
 }
@@ -0,0 +1,217 @@
+use glib::object::IsA;
+use gtk4::prelude::{SnapshotExt, TextureExt, WidgetExt};
+
+use crate::capture::{CaptureBoxed, CapturedOutput};
+
+use super::selection::{Selection, SelectionConstraint};
+
+/// Screen pixels per captured pixel.
+const ZOOM: f32 = 8.0;
+/// Captured pixels shown across the loupe, odd so one sits in the middle.
+const PIXELS_ACROSS: f32 = 17.0;
+const LOUPE_SIZE: f32 = ZOOM * PIXELS_ACROSS;
+/// Distance between the pointer and the loupe, so it does not hide what is
+/// being pointed at.
+const POINTER_OFFSET: f32 = 24.0;
+const READOUT_PADDING: f32 = 4.0;
+
+/// The captured pixels, downloaded once per output the first time the
+/// loupe needs to read a color from it.
+#[derive(Debug, Default)]
+pub struct PixelCache(Vec<Option<Vec<u8>>>);
+
+impl PixelCache {
+    /// Forgets the pixels, for when the capture changes.
+    pub fn clear(&mut self) {
+        self.0.clear();
+    }
+
+    /// Returns the premultiplied ARGB color of the captured pixel at
+    /// `point`, in the compositor's global space.
+    pub fn color_at(&mut self, capture: &CaptureBoxed, point: (f32, f32)) -> Option<u32> {
+        let (index, output) = capture
+            .iter()
+            .enumerate()
+            .find(|(_, output)| output.contains(point))?;
+        if self.0.len() != capture.len() {
+            self.0 = vec![None; capture.len()];
+        }
+
+        let width = output.texture.width() as usize;
+        let height = output.texture.height() as usize;
+        let stride = width * 4;
+        // gdk_texture_download produces cairo's native ARGB32 layout.
+        let data = self.0[index].get_or_insert_with(|| {
+            let mut data = vec![0u8; stride * height];
+            output.texture.download(&mut data, stride);
+            data
+        });
+
+        let (pixel_x, pixel_y) = texture_pixel(output, point);
+        let offset = pixel_y.min(height.saturating_sub(1)) * stride
+            + pixel_x.min(width.saturating_sub(1)) * 4;
+        let pixel = data.get(offset..offset + 4)?;
+        Some(u32::from_ne_bytes([pixel[0], pixel[1], pixel[2], pixel[3]]))
+    }
+}
+
+/// The texture pixel of `output` under `point`, in global logical space.
+fn texture_pixel(output: &CapturedOutput, point: (f32, f32)) -> (usize, usize) {
+    let scale = output.scale() as f32;
+    let x = ((point.0 - output.x as f32) * scale).floor().max(0.0);
+    let y = ((point.1 - output.y as f32) * scale).floor().max(0.0);
+    (x as usize, y as usize)
+}
+
+/// Formats a premultiplied ARGB color as `#RRGGBB`.
+fn hex_color(pixel: u32) -> String {
+    let alpha = pixel >> 24;
+    let unpremultiply = |channel: u32| {
+        if alpha == 0 {
+            0
+        } else {
+            ((channel * 255 + alpha / 2) / alpha).min(255)
+        }
+    };
+    format!(
+        "#{:02X}{:02X}{:02X}",
+        unpremultiply((pixel >> 16) & 0xFF),
+        unpremultiply((pixel >> 8) & 0xFF),
+        unpremultiply(pixel & 0xFF),
+    )
+}
+
+/// Draws the loupe next to `pointer`, which is in widget coordinates.
+///
+/// It shows the captured pixels around the pointer magnified with a grid
+/// between them, and below that the global coordinates of the pointer,
+/// the size of `selection` and the color under the pointer.
+pub fn draw_to_snapshot(
+    snapshot: &gtk4::Snapshot,
+    widget: impl IsA<gtk4::Widget>,
+    capture: &CaptureBoxed,
+    origin: (f32, f32),
+    pointer: (f32, f32),
+    selection: Option<&Selection>,
+    color: Option<u32>,
+) {
+    let global = (pointer.0 + origin.0, pointer.1 + origin.1);
+
+    let mut readout = format!("{}, {}", global.0.floor(), global.1.floor());
+    if let Some(selection) = selection {
+        let (width, height) = selection.size();
+        readout.push_str(&format!("\n{} × {}", width.round(), height.round()));
+        if selection.constraint() != SelectionConstraint::Free {
+            readout.push_str(&format!(" ({})", selection.constraint()));
+        }
+    }
+    if let Some(color) = color {
+        readout.push('\n');
+        readout.push_str(&hex_color(color));
+    }
+    let layout = widget.create_pango_layout(Some(&readout));
+    let (text_width, text_height) = layout.pixel_size();
+    let readout_height = text_height as f32 + 2.0 * READOUT_PADDING;
+
+    // Below and to the right of the pointer, unless that goes off the widget.
+    let total_height = LOUPE_SIZE + readout_height;
+    let mut x = pointer.0 + POINTER_OFFSET;
+    if x + LOUPE_SIZE > widget.width() as f32 {
+        x = pointer.0 - POINTER_OFFSET - LOUPE_SIZE;
+    }
+    let mut y = pointer.1 + POINTER_OFFSET;
+    if y + total_height > widget.height() as f32 {
+        y = pointer.1 - POINTER_OFFSET - total_height;
+    }
+    let loupe = graphene::Rect::new(x.round(), y.round(), LOUPE_SIZE, LOUPE_SIZE);
+
+    snapshot.push_clip(&loupe);
+    snapshot.append_color(&gdk4::RGBA::BLACK, &loupe);
+    if let Some(output) = capture.iter().find(|output| output.contains(global)) {
+        draw_magnified(snapshot, capture, output, origin, global, &loupe);
+    }
+    snapshot.pop();
+    draw_grid(snapshot, &loupe);
+
+    let readout_rect = graphene::Rect::new(
+        loupe.x(),
+        loupe.y() + LOUPE_SIZE,
+        LOUPE_SIZE.max(text_width as f32 + 2.0 * READOUT_PADDING),
+        readout_height,
+    );
+    snapshot.append_color(&gdk4::RGBA::new(0.0, 0.0, 0.0, 0.7), &readout_rect);
+    snapshot.save();
+    snapshot.translate(&graphene::Point::new(
+        readout_rect.x() + READOUT_PADDING,
+        readout_rect.y() + READOUT_PADDING,
+    ));
+    snapshot.append_layout(&layout, &gdk4::RGBA::WHITE);
+    snapshot.restore();
+}
+
+/// Draws every output magnified so the texture pixel of `output` under
+/// `global` fills the middle cell of the loupe.
+fn draw_magnified(
+    snapshot: &gtk4::Snapshot,
+    capture: &CaptureBoxed,
+    output: &CapturedOutput,
+    origin: (f32, f32),
+    global: (f32, f32),
+    loupe: &graphene::Rect,
+) {
+    let scale = output.scale() as f32;
+    let (pixel_x, pixel_y) = texture_pixel(output, global);
+    // Center of that pixel, relative to the widget.
+    let center_x = output.x as f32 + (pixel_x as f32 + 0.5) / scale - origin.0;
+    let center_y = output.y as f32 + (pixel_y as f32 + 0.5) / scale - origin.1;
+
+    snapshot.save();
+    snapshot.translate(&graphene::Point::new(
+        loupe.x() + LOUPE_SIZE / 2.0,
+        loupe.y() + LOUPE_SIZE / 2.0,
+    ));
+    snapshot.scale(ZOOM * scale, ZOOM * scale);
+    snapshot.translate(&graphene::Point::new(-center_x, -center_y));
+    for output in capture.iter() {
+        let bounds = graphene::Rect::new(
+            output.x as f32 - origin.0,
+            output.y as f32 - origin.1,
+            output.width as f32,
+            output.height as f32,
+        );
+        // Nearest neighbour, the whole point is seeing individual pixels.
+        snapshot.append_scaled_texture(&output.texture, gsk4::ScalingFilter::Nearest, &bounds);
+    }
+    snapshot.restore();
+}
+
+/// Draws the lines between magnified pixels, the middle one outlined.
+fn draw_grid(snapshot: &gtk4::Snapshot, loupe: &graphene::Rect) {
+    let path_builder = gsk4::PathBuilder::new();
+    for step in 0..=PIXELS_ACROSS as u32 {
+        let offset = step as f32 * ZOOM;
+        path_builder.move_to(loupe.x() + offset, loupe.y());
+        path_builder.line_to(loupe.x() + offset, loupe.y() + LOUPE_SIZE);
+        path_builder.move_to(loupe.x(), loupe.y() + offset);
+        path_builder.line_to(loupe.x() + LOUPE_SIZE, loupe.y() + offset);
+    }
+    let grid = path_builder.to_path();
+    let grid_color = gdk4::RGBA::new(0.5, 0.5, 0.5, 0.4);
+    snapshot.append_stroke(&grid, &gsk4::Stroke::new(1.0), &grid_color);
+
+    let middle = (LOUPE_SIZE - ZOOM) / 2.0;
+    let path_builder = gsk4::PathBuilder::new();
+    path_builder.add_rect(&graphene::Rect::new(
+        loupe.x() + middle,
+        loupe.y() + middle,
+        ZOOM,
+        ZOOM,
+    ));
+    let cell = path_builder.to_path();
+    snapshot.append_stroke(&cell, &gsk4::Stroke::new(2.0), &gdk4::RGBA::WHITE);
+
+    let path_builder = gsk4::PathBuilder::new();
+    path_builder.add_rect(loupe);
+    let border = path_builder.to_path();
+    snapshot.append_stroke(&border, &gsk4::Stroke::new(1.0), &gdk4::RGBA::WHITE);
+}
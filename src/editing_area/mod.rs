@@ -1,34 +1,125 @@
+mod annotation;
 mod command;
+mod loupe;
+mod redact;
+mod rich_text;
 mod selection;
+mod text_edit;
+mod tool;
 
-pub use command::{Command, CommandType, CommandsBoxed};
+pub use command::{number_steps, Change, Command, CommandType, CommandsBoxed};
+pub use selection::{MaybeSelection, MaybeSelectionBoxed, Selection, SelectionConstraint};
+
+use annotation::{CommandEdit, CommandHit};
+use selection::{SelectionHit, SnapTargets};
+use rich_text::Emphasis;
+use text_edit::TextEdit;
+use tool::Tool;
+
+use std::path::{Path, PathBuf};
+
+use gtk4::prelude::*;
+use gtk4::subclass::prelude::*;
+
+use crate::capture::WindowGeometry;
+use crate::clipboard;
+use crate::export::{self, ExportError, ExportFormat, ExportOptions, Pixels};
+use crate::render::{self, RenderError};
 
 mod imp {
-    use std::cell::RefCell;
+    use std::cell::{Cell, RefCell};
+    use std::marker::PhantomData;
     use std::sync::atomic::AtomicBool;
+    use std::sync::OnceLock;
 
+    use glib::subclass::Signal;
     use glib::Properties;
     use gtk4::glib;
     use gtk4::subclass::prelude::*;
     use gtk4::prelude::*;
 
-    use crate::editing_area::selection::{MaybeSelection, Selection};
+    use crate::capture::{CaptureBoxed, WindowGeometry};
+    use crate::editing_area::selection::{
+        DragHandle, MaybeSelection, Selection, SelectionConstraint, SelectionHit,
+    };
 
-    use super::command::CommandsBoxed;
-    use super::selection::MaybeSelectionBoxed;
+    use super::annotation::CommandEdit;
+    use super::command::{self, Command, CommandsBoxed};
+    use super::loupe::{self, PixelCache};
+    use super::selection::{self, MaybeSelectionBoxed};
+    use super::text_edit::TextEdit;
+    use super::tool::{Tool, ToolStyle, CALLOUT_CLICK_OFFSET};
 
 
     #[derive(Properties, Default, Debug)]
     #[properties(wrapper_type = super::EditingArea)]
     pub struct EditingArea {
-        #[property(get, set)]
+        #[property(get, set = Self::set_undo_stack)]
         pub undo_stack: RefCell<CommandsBoxed>,
-        // #[property(get, set)]
-        // pub redo_stack: RefCell<CommandsBoxed>,
+        /// Commands taken off the undo stack, most recently undone last.
+        #[property(get, set = Self::set_redo_stack)]
+        pub redo_stack: RefCell<CommandsBoxed>,
+        #[property(get = Self::can_undo)]
+        can_undo: PhantomData<bool>,
+        #[property(get = Self::can_redo)]
+        can_redo: PhantomData<bool>,
         #[property(get, set)]
         pub selection: RefCell<MaybeSelectionBoxed>,
         #[property(get, set)]
         pub active_drag: AtomicBool,
+        /// Limits on the selection's shape. Shift locks the ratio while
+        /// dragging when this is free.
+        #[property(get, set = Self::set_constraint)]
+        pub constraint: RefCell<SelectionConstraint>,
+        /// What dragging does.
+        #[property(get, set, builder(Tool::default()))]
+        pub tool: Cell<Tool>,
+        /// How new commands look.
+        #[property(get, set)]
+        pub tool_style: RefCell<ToolStyle>,
+        /// The command being drawn, not on the undo stack until the drag ends.
+        pub preview: RefCell<Option<Command>>,
+        /// Index on the undo stack of the change that added the command
+        /// picked with the selection tool.
+        pub selected_command: Cell<Option<usize>>,
+        /// The picked command while it is being moved or resized.
+        pub command_edit: RefCell<Option<CommandEdit>>,
+        /// The text command being typed in.
+        pub text_edit: RefCell<Option<TextEdit>>,
+        /// Composes what is typed into the text being edited.
+        pub im_context: gtk4::IMMulticontext,
+        /// The part of the selection being dragged, `None` when dragging out
+        /// a new one.
+        pub selection_drag: Cell<Option<SelectionHit>>,
+        /// The selection as it was when the drag started, moved by the
+        /// whole drag offset so snapping does not accumulate.
+        pub drag_start_selection: Cell<Option<Selection>>,
+        /// Visible windows in global coordinates, to snap to and select.
+        pub windows: RefCell<Vec<WindowGeometry>>,
+        /// The window or output a click would select, in widget coordinates.
+        pub hovered_rect: RefCell<Option<graphene::Rect>>,
+        /// The handle Ctrl+arrows reshape the selection by, cycled with Tab.
+        pub active_handle: Cell<DragHandle>,
+        /// The frozen screen everything else is drawn on top of.
+        #[property(get, set = Self::set_capture)]
+        pub capture: RefCell<CaptureBoxed>,
+        /// Pixels of the capture the loupe reads colors from.
+        pub pixel_cache: RefCell<PixelCache>,
+        /// Where the pointer is during a drag, for the loupe.
+        pub pointer: Cell<Option<(f32, f32)>>,
+        /// Logical position of the widget's top-left corner in the
+        /// compositor's global space, used to line up the capture.
+        #[property(get, set)]
+        pub origin_x: Cell<i32>,
+        #[property(get, set)]
+        pub origin_y: Cell<i32>,
+        /// Image pixels per logical pixel when rendering the result, 0 for
+        /// the highest scale of the captured outputs.
+        #[property(get, set, minimum = 0.0)]
+        pub export_scale: Cell<f64>,
+        /// The last PNG put on the clipboard, kept to hand it over to the
+        /// clipboard server when we exit.
+        pub copied: RefCell<Option<glib::Bytes>>,
     }
 
     #[glib::object_subclass]
@@ -36,14 +127,113 @@ mod imp {
         const NAME: &'static str = "WEditingArea";
         type Type = super::EditingArea;
         type ParentType = gtk4::Widget;
+
+        fn class_init(klass: &mut Self::Class) {
+            klass.install_action("editing-area.undo", None, |editing_area, _, _| {
+                editing_area.undo();
+            });
+            klass.add_binding_action(
+                gdk4::Key::z,
+                gdk4::ModifierType::CONTROL_MASK,
+                "editing-area.undo",
+            );
+
+            klass.install_action("editing-area.redo", None, |editing_area, _, _| {
+                editing_area.redo();
+            });
+            klass.add_binding_action(
+                gdk4::Key::z,
+                gdk4::ModifierType::CONTROL_MASK | gdk4::ModifierType::SHIFT_MASK,
+                "editing-area.redo",
+            );
+
+            klass.install_action("editing-area.save", None, |editing_area, _, _| {
+                match editing_area.save_to_pictures(&crate::export::ExportOptions::default()) {
+                    Ok(path) => eprintln!("Saved to {}", path.display()),
+                    Err(error) => eprintln!("Could not save the screenshot: {}", error),
+                }
+            });
+            klass.add_binding_action(
+                gdk4::Key::s,
+                gdk4::ModifierType::CONTROL_MASK,
+                "editing-area.save",
+            );
+
+            for (key, tool) in [
+                (gdk4::Key::s, Tool::Selection),
+                (gdk4::Key::r, Tool::Rectangle),
+                (gdk4::Key::c, Tool::Circle),
+                (gdk4::Key::l, Tool::Line),
+                (gdk4::Key::a, Tool::Arrow),
+                (gdk4::Key::f, Tool::Freehand),
+                (gdk4::Key::t, Tool::Text),
+                (gdk4::Key::b, Tool::Blur),
+                (gdk4::Key::m, Tool::Pixelate),
+                (gdk4::Key::x, Tool::Redact),
+                (gdk4::Key::n, Tool::StepMarker),
+                (gdk4::Key::o, Tool::Callout),
+            ] {
+                klass.add_binding(key, gdk4::ModifierType::empty(), move |editing_area| {
+                    editing_area.set_tool(tool);
+                    glib::Propagation::Stop
+                });
+            }
+
+            klass.install_action("editing-area.next-constraint", None, |editing_area, _, _| {
+                // Shown in a label on the selection.
+                let constraint = editing_area.constraint().next_preset();
+                editing_area.set_constraint(constraint);
+            });
+            klass.add_binding_action(
+                gdk4::Key::p,
+                gdk4::ModifierType::empty(),
+                "editing-area.next-constraint",
+            );
+
+            klass.install_action("editing-area.confirm", None, |editing_area, _, _| {
+                editing_area.end_text_edit();
+                editing_area.emit_by_name::<()>("confirmed", &[]);
+            });
+
+            klass.install_action("editing-area.cancel", None, |editing_area, _, _| {
+                editing_area.emit_by_name::<()>("cancelled", &[]);
+            });
+
+            klass.install_action("editing-area.copy", None, |editing_area, _, _| {
+                if let Err(error) = editing_area.copy_to_clipboard() {
+                    eprintln!("Could not copy the screenshot: {}", error);
+                }
+            });
+            klass.add_binding_action(
+                gdk4::Key::c,
+                gdk4::ModifierType::CONTROL_MASK,
+                "editing-area.copy",
+            );
+        }
     }
 
     #[glib::derived_properties]
     impl ObjectImpl for EditingArea {
+        fn signals() -> &'static [Signal] {
+            static SIGNALS: OnceLock<Vec<Signal>> = OnceLock::new();
+            SIGNALS.get_or_init(|| {
+                vec![
+                    // The user is done and wants the result.
+                    Signal::builder("confirmed").build(),
+                    // The user wants out without a result.
+                    Signal::builder("cancelled").build(),
+                ]
+            })
+        }
+
         fn constructed(&self) {
             self.parent_constructed();
+            self.stacks_changed();
 
             let editing_area = self.obj().clone();
+            editing_area.set_focusable(true);
+            // The selection may be changed by an editing area on another output.
+            editing_area.connect_selection_notify(|editing_area| editing_area.queue_draw());
             let editing_area_weak = editing_area.downgrade();
 
             let drag_gesture = gtk4::GestureDrag::new();
@@ -53,31 +243,131 @@ mod imp {
                 let Some(editing_area) = editing_area_weak_clone.upgrade() else {
                     return;
                 };
-                println!("Drag started at ({}, {})", x, y);
                 let x = x as f32;
                 let y = y as f32;
                 editing_area.set_active_drag(true);
+                editing_area.imp().pointer.set(Some((x, y)));
 
-                let selection = Selection::new((x, y), (x, y));
+                // A click on the text being typed moves the caret, anywhere
+                // else ends the edit.
+                if editing_area.text_edit_contains((x, y)) {
+                    editing_area.move_text_caret((x, y), false);
+                    return;
+                }
+                editing_area.end_text_edit();
+
+                let tool = editing_area.tool();
+                if let Some(command) = tool.begin_command((x, y), &editing_area.tool_style()) {
+                    if tool == Tool::Text {
+                        editing_area.begin_text_edit(None, command);
+                        return;
+                    }
+                    editing_area.imp().preview.replace(Some(command));
+                    editing_area.queue_draw();
+                    return;
+                }
+                // Picking a command comes before the selection, which is
+                // usually around it.
+                if editing_area.begin_command_edit((x, y)) {
+                    editing_area.queue_draw();
+                    return;
+                }
+
+                let (hit, start_selection) = match editing_area.selection().0 {
+                    MaybeSelection::Selection(selection) => (selection.hit_test((x, y)), Some(selection)),
+                    MaybeSelection::NoSelection(_) => (None, None),
+                };
+                let imp = editing_area.imp();
+                imp.selection_drag.set(hit);
+                imp.drag_start_selection.set(start_selection.filter(|_| hit.is_some()));
+                imp.hovered_rect.replace(None);
+                if hit.is_some() {
+                    return;
+                }
+
+                let mut selection = Selection::new((x, y), (x, y));
+                selection.set_constraint(editing_area.constraint());
+                selection.drag_end_to((x, y));
                 let selection_boxed: MaybeSelectionBoxed = MaybeSelection::Selection(selection).into();
                 editing_area.set_selection(selection_boxed);
                 editing_area.queue_draw();
             });
             
             editing_area_weak_clone = editing_area_weak.clone();
-            drag_gesture.connect_drag_update(move |_, x, y| {
+            drag_gesture.connect_drag_update(move |gesture, x, y| {
                 let Some(editing_area) = editing_area_weak_clone.upgrade() else {
                     return;
                 };
-                println!("Drag updated at ({}, {})", x, y);
                 let x = x as f32;
                 let y = y as f32;
-                let maybe_selection = editing_area.selection();
+                if let Some((start_x, start_y)) = gesture.start_point() {
+                    let pointer = (start_x as f32 + x, start_y as f32 + y);
+                    editing_area.imp().pointer.set(Some(pointer));
+                }
 
-                let sel = match maybe_selection.0 {
-                    MaybeSelection::Selection(mut sel) => {
+                if let Some(command) = editing_area.imp().preview.borrow_mut().as_mut() {
+                    let Some((start_x, start_y)) = gesture.start_point() else {
+                        return;
+                    };
+                    command.drag_to((start_x as f32 + x, start_y as f32 + y));
+                    editing_area.queue_draw();
+                    return;
+                }
+                if editing_area.imp().text_edit.borrow().is_some() {
+                    if let Some((start_x, start_y)) = gesture.start_point() {
+                        editing_area.move_text_caret((start_x as f32 + x, start_y as f32 + y), true);
+                    }
+                    return;
+                }
+                if let Some(edit) = editing_area.imp().command_edit.borrow_mut().as_mut() {
+                    let Some((start_x, start_y)) = gesture.start_point() else {
+                        return;
+                    };
+                    edit.drag_to((x, y), (start_x as f32 + x, start_y as f32 + y), editing_area.clone());
+                    editing_area.queue_draw();
+                    return;
+                }
+
+                let mut maybe_selection = editing_area.selection();
+                let imp = editing_area.imp();
+                let targets = editing_area.snap_targets();
+
+                // Shift locks the ratio the selection had when the drag started.
+                let shift = gesture.current_event_state().contains(gdk4::ModifierType::SHIFT_MASK);
+                let constraint = match editing_area.constraint() {
+                    SelectionConstraint::Free if shift => {
+                        let ratio = imp.drag_start_selection.get().map_or(1.0, |selection| selection.ratio());
+                        SelectionConstraint::Ratio(ratio)
+                    }
+                    constraint => constraint,
+                };
+                if let MaybeSelection::Selection(selection) = &mut maybe_selection.0 {
+                    selection.set_constraint(constraint);
+                }
+
+                let sel = match (maybe_selection.0, imp.selection_drag.get()) {
+                    (MaybeSelection::Selection(mut sel), Some(SelectionHit::Handle(handle))) => {
+                        let Some((start_x, start_y)) = gesture.start_point() else {
+                            return;
+                        };
+                        let point = (start_x as f32 + x, start_y as f32 + y);
+                        sel.reshape(handle, point.0, point.1);
+                        let handle = handle.after_reshape(&sel, point);
+                        sel.snap_handle(handle, &targets);
+                        imp.selection_drag.set(Some(SelectionHit::Handle(handle)));
+                        imp.active_handle.set(handle);
+                        sel
+                    }
+                    (MaybeSelection::Selection(sel), Some(SelectionHit::Interior)) => {
+                        let mut moved = imp.drag_start_selection.get().unwrap_or(sel);
+                        moved.move_relative(x, y);
+                        moved.snap_move(&targets);
+                        moved
+                    }
+                    (MaybeSelection::Selection(mut sel), None) => {
                         let start = sel.start();
-                        sel.set_end((start.0 + x, start.1 + y));
+                        sel.drag_end_to((start.0 + x, start.1 + y));
+                        sel.snap_end(&targets);
                         sel
                     }
                     _ => {
@@ -86,35 +376,342 @@ mod imp {
                 };
                 editing_area.set_selection(MaybeSelectionBoxed::from(MaybeSelection::Selection(sel)));
                 editing_area.queue_draw();
+            });
 
-                dbg!(editing_area.selection());
+            editing_area_weak_clone = editing_area_weak.clone();
+            drag_gesture.connect_drag_end(move |gesture, x, y| {
+                let Some(editing_area) = editing_area_weak_clone.upgrade() else {
+                    return;
+                };
+                editing_area.set_active_drag(false);
+                let imp = editing_area.imp();
+                if imp.text_edit.borrow().is_some() {
+                    imp.pointer.set(None);
+                    editing_area.queue_draw();
+                    return;
+                }
+                if let Some(edit) = imp.command_edit.take() {
+                    if edit.command != edit.original {
+                        editing_area.push_change(Change::Replace {
+                            index: edit.index,
+                            command: Some(edit.command),
+                        });
+                    }
+                    imp.pointer.set(None);
+                    editing_area.queue_draw();
+                    return;
+                }
+                let fresh_selection = imp.preview.borrow().is_none() && imp.selection_drag.get().is_none();
+                // Drop the ratio Shift may have locked.
+                let mut maybe_selection = editing_area.selection();
+                if let MaybeSelection::Selection(selection) = &mut maybe_selection.0 {
+                    selection.set_constraint(editing_area.constraint());
+                    editing_area.set_selection(maybe_selection);
+                }
+                imp.selection_drag.set(None);
+                imp.pointer.set(None);
+
+                // A click instead of a drag selects the window or output under it.
+                if fresh_selection && x.abs() < 1.0 && y.abs() < 1.0 {
+                    if let Some((start_x, start_y)) = gesture.start_point() {
+                        editing_area.select_rect_at((start_x as f32, start_y as f32));
+                    }
+                }
+
+                let preview = editing_area.imp().preview.take();
+                match preview {
+                    // A callout's text is typed in once its box is placed.
+                    Some(mut command) if command.has_text() => {
+                        // A click puts the box off to the side of what it
+                        // points at.
+                        if x.abs() < 1.0 && y.abs() < 1.0 {
+                            let start = command.start;
+                            command.drag_to((start.0 + CALLOUT_CLICK_OFFSET.0, start.1 + CALLOUT_CLICK_OFFSET.1));
+                        }
+                        editing_area.begin_text_edit(None, command);
+                        return;
+                    }
+                    Some(command) if !command.is_degenerate() => editing_area.push_command(command),
+                    _ => {}
+                }
                 editing_area.queue_draw();
             });
 
+            // Show what dragging would do under the pointer.
+            let motion_controller = gtk4::EventControllerMotion::new();
             editing_area_weak_clone = editing_area_weak.clone();
-            drag_gesture.connect_drag_end(move |_, x, y| {
+            motion_controller.connect_motion(move |_, x, y| {
                 let Some(editing_area) = editing_area_weak_clone.upgrade() else {
                     return;
                 };
-                println!("Drag ended at ({}, {})", x, y);
-                editing_area.set_active_drag(false);
+                if editing_area.active_drag() {
+                    return;
+                }
+                editing_area.update_cursor((x as f32, y as f32));
+            });
+            editing_area_weak_clone = editing_area_weak.clone();
+            motion_controller.connect_leave(move |_| {
+                let Some(editing_area) = editing_area_weak_clone.upgrade() else {
+                    return;
+                };
+                editing_area.imp().hovered_rect.replace(None);
                 editing_area.queue_draw();
             });
 
+            // Double-clicking a text command types into it again.
+            let click_gesture = gtk4::GestureClick::new();
+            editing_area_weak_clone = editing_area_weak.clone();
+            click_gesture.connect_pressed(move |_, n_press, x, y| {
+                let Some(editing_area) = editing_area_weak_clone.upgrade() else {
+                    return;
+                };
+                if n_press == 2 {
+                    editing_area.reopen_text_at((x as f32, y as f32));
+                }
+            });
+
+            // Typing goes through the input method before any shortcut, but
+            // only while a text is being edited.
+            let text_key_controller = gtk4::EventControllerKey::new();
+            text_key_controller.set_propagation_phase(gtk4::PropagationPhase::Capture);
+            editing_area_weak_clone = editing_area_weak.clone();
+            text_key_controller.connect_key_pressed(move |controller, key, _, modifiers| {
+                let Some(editing_area) = editing_area_weak_clone.upgrade() else {
+                    return glib::Propagation::Proceed;
+                };
+                editing_area.handle_text_key(controller, key, modifiers)
+            });
+            editing_area_weak_clone = editing_area_weak.clone();
+            text_key_controller.connect_key_released(move |controller, _, _, _| {
+                let Some(editing_area) = editing_area_weak_clone.upgrade() else {
+                    return;
+                };
+                let imp = editing_area.imp();
+                if let Some(event) = controller.current_event().filter(|_| imp.text_edit.borrow().is_some()) {
+                    imp.im_context.filter_keypress(event);
+                }
+            });
+
+            let im_context = &self.im_context;
+            im_context.set_client_widget(Some(&editing_area));
+            editing_area_weak_clone = editing_area_weak.clone();
+            im_context.connect_commit(move |_, text| {
+                if let Some(editing_area) = editing_area_weak_clone.upgrade() {
+                    editing_area.insert_text(text);
+                }
+            });
+            editing_area_weak_clone = editing_area_weak.clone();
+            im_context.connect_preedit_changed(move |im_context| {
+                let Some(editing_area) = editing_area_weak_clone.upgrade() else {
+                    return;
+                };
+                let (preedit, _, cursor) = im_context.preedit_string();
+                if let Some(edit) = editing_area.imp().text_edit.borrow_mut().as_mut() {
+                    // The input method counts characters, not bytes.
+                    edit.preedit_cursor = preedit
+                        .char_indices()
+                        .nth(cursor.max(0) as usize)
+                        .map_or(preedit.len(), |(index, _)| index);
+                    edit.preedit = preedit.to_string();
+                }
+                editing_area.text_edit_changed();
+            });
+            editing_area_weak_clone = editing_area_weak.clone();
+            im_context.connect_retrieve_surrounding(move |im_context| {
+                let Some(editing_area) = editing_area_weak_clone.upgrade() else {
+                    return false;
+                };
+                let text_edit = editing_area.imp().text_edit.borrow();
+                let Some(edit) = text_edit.as_ref() else {
+                    return false;
+                };
+                im_context.set_surrounding_with_selection(edit.text(), edit.cursor as i32, edit.anchor as i32);
+                true
+            });
+            editing_area_weak_clone = editing_area_weak.clone();
+            im_context.connect_delete_surrounding(move |_, offset, count| {
+                let Some(editing_area) = editing_area_weak_clone.upgrade() else {
+                    return false;
+                };
+                let deleted = editing_area
+                    .imp()
+                    .text_edit
+                    .borrow_mut()
+                    .as_mut()
+                    .is_some_and(|edit| edit.delete_surrounding(offset, count));
+                editing_area.text_edit_changed();
+                deleted
+            });
+            // Switching tools is done typing.
+            editing_area.connect_tool_notify(|editing_area| editing_area.end_text_edit());
+
+            let key_controller = gtk4::EventControllerKey::new();
+            editing_area_weak_clone = editing_area_weak.clone();
+            key_controller.connect_key_pressed(move |_, key, _, modifiers| {
+                let Some(editing_area) = editing_area_weak_clone.upgrade() else {
+                    return glib::Propagation::Proceed;
+                };
+                editing_area.handle_key(key, modifiers)
+            });
+
             drop(editing_area_weak);
             editing_area.add_controller(drag_gesture);
+            editing_area.add_controller(click_gesture);
+            editing_area.add_controller(motion_controller);
+            editing_area.add_controller(text_key_controller);
+            editing_area.add_controller(key_controller);
+        }
+    }
+
+    impl EditingArea {
+        fn set_constraint(&self, constraint: SelectionConstraint) {
+            self.constraint.replace(constraint);
+            let mut selection = self.selection.borrow_mut();
+            if let MaybeSelection::Selection(selection) = &mut selection.0 {
+                selection.set_constraint(constraint);
+                selection.enforce_constraint();
+            }
+            drop(selection);
+            self.obj().notify_selection();
+            self.obj().queue_draw();
+        }
+
+        fn set_capture(&self, capture: CaptureBoxed) {
+            self.capture.replace(capture);
+            self.pixel_cache.borrow_mut().clear();
+            self.obj().queue_draw();
+        }
+
+        fn set_undo_stack(&self, undo_stack: CommandsBoxed) {
+            self.undo_stack.replace(undo_stack);
+            self.stacks_changed();
+        }
+
+        fn set_redo_stack(&self, redo_stack: CommandsBoxed) {
+            self.redo_stack.replace(redo_stack);
+            self.stacks_changed();
+        }
+
+        fn can_undo(&self) -> bool {
+            !self.undo_stack.borrow().is_empty()
+        }
+
+        fn can_redo(&self) -> bool {
+            !self.redo_stack.borrow().is_empty()
+        }
+
+        /// Keeps everything that depends on the stacks in sync, to be
+        /// called after touching either of them.
+        pub(super) fn stacks_changed(&self) {
+            let obj = self.obj();
+            // Undoing may have taken the picked command away.
+            if let Some(index) = self.selected_command.get() {
+                if self.undo_stack.borrow().command(index).is_none() {
+                    self.selected_command.set(None);
+                }
+            }
+            obj.action_set_enabled("editing-area.undo", self.can_undo());
+            obj.action_set_enabled("editing-area.redo", self.can_redo());
+            // The stacks are often changed in place, tell the bindings to
+            // other outputs' editing areas.
+            obj.notify_undo_stack();
+            obj.notify_redo_stack();
+            obj.notify_can_undo();
+            obj.notify_can_redo();
+            obj.queue_draw();
         }
     }
 
     impl WidgetImpl for EditingArea {
         fn snapshot(&self, snapshot: &gtk4::Snapshot) {
+            let origin = (self.origin_x.get() as f32, self.origin_y.get() as f32);
+            // Each command is drawn over a node of everything before it,
+            // which redactions blur or pixelate.
             let undo_stack = self.undo_stack.borrow();
-            for command in undo_stack.iter() {
-                command.draw_to_snapshot(snapshot, self.obj().clone());
+            let command_edit = self.command_edit.borrow();
+            let text_edit = self.text_edit.borrow();
+            let typed = text_edit.as_ref().map(TextEdit::displayed);
+            // Commands being moved, resized or typed in are shown as they
+            // are now.
+            let commands: Vec<&Command> = undo_stack
+                .resolve()
+                .into_iter()
+                .map(|(index, command)| match (command_edit.as_ref(), text_edit.as_ref(), typed.as_ref()) {
+                    (Some(edit), _, _) if edit.index == index => &edit.command,
+                    (_, Some(edit), Some(typed)) if edit.index == Some(index) => typed,
+                    _ => command,
+                })
+                .collect();
+            let new_text = typed.as_ref().filter(|_| text_edit.as_ref().is_some_and(|edit| edit.index.is_none()));
+
+            let layer = gtk4::Snapshot::new();
+            self.capture.borrow().draw_to_snapshot(&layer, origin);
+            for command in commands.iter().filter(|command| command.is_redact_box()) {
+                command.draw_to_snapshot(&layer, self.obj().clone(), None, None);
+            }
+            let mut below = layer.to_node();
+
+            let preview = self.preview.borrow();
+            let commands_shown = commands.iter().copied().chain(preview.as_ref()).chain(new_text);
+            for (command, step) in command::number_steps(commands_shown) {
+                let layer = gtk4::Snapshot::new();
+                if let Some(below) = &below {
+                    layer.append_node(below);
+                }
+                command.draw_to_snapshot(&layer, self.obj().clone(), below.as_ref(), step);
+                below = layer.to_node();
+            }
+            if let Some(below) = below {
+                snapshot.append_node(below);
+            }
+
+            let maybe_selection = self.selection.borrow();
+            maybe_selection.draw_to_snapshot(snapshot, self.obj().clone());
+            let selection = match &maybe_selection.0 {
+                MaybeSelection::Selection(selection) => Some(selection),
+                MaybeSelection::NoSelection(_) => None,
+            };
+            if let Some(selection) = selection {
+                selection.draw_active_handle_to_snapshot(
+                    snapshot,
+                    self.obj().clone(),
+                    self.active_handle.get(),
+                );
+                selection.draw_constraint_to_snapshot(snapshot, self.obj().clone());
             }
-            
 
-            self.selection.borrow().draw_to_snapshot(snapshot, self.obj().clone());
+            if let Some(bounds) = self.obj().selected_command_bounds() {
+                bounds.draw_frame_to_snapshot(snapshot, self.obj().clone());
+            }
+            if let Some(anchor) = self.obj().selected_command_anchor() {
+                selection::draw_knob_to_snapshot(snapshot, self.obj().clone(), anchor);
+            }
+            if let Some(edit) = text_edit.as_ref() {
+                edit.draw_to_snapshot(snapshot, self.obj().clone());
+            }
+
+            if let Some(rect) = self.hovered_rect.borrow().as_ref() {
+                let accent = gdk4::RGBA::new(0.21, 0.52, 0.89, 1.0);
+                snapshot.append_color(&gdk4::RGBA::new(0.21, 0.52, 0.89, 0.2), rect);
+                let path_builder = gsk4::PathBuilder::new();
+                path_builder.add_rect(rect);
+                snapshot.append_stroke(&path_builder.to_path(), &gsk4::Stroke::new(2.0), &accent);
+            }
+
+            if let Some(pointer) = self.pointer.get().filter(|_| self.obj().active_drag()) {
+                let capture = self.capture.borrow();
+                let global = (pointer.0 + origin.0, pointer.1 + origin.1);
+                let color = self.pixel_cache.borrow_mut().color_at(&capture, global);
+                loupe::draw_to_snapshot(
+                    snapshot,
+                    self.obj().clone(),
+                    &capture,
+                    origin,
+                    pointer,
+                    selection,
+                    color,
+                );
+            }
         }
     }
 }
@@ -129,4 +726,598 @@ impl EditingArea {
     pub fn new() -> Self {
         glib::Object::new::<Self>()
     }
+
+    /// Keyboard control of the selection: arrows move it, Ctrl+arrows
+    /// reshape it by the active handle, Shift makes steps 10px instead of
+    /// 1px and Tab picks the active handle. Delete removes the picked
+    /// command, and minus and plus renumber a picked step marker.
+    fn handle_key(&self, key: gdk4::Key, modifiers: gdk4::ModifierType) -> glib::Propagation {
+        match key {
+            gdk4::Key::Return | gdk4::Key::KP_Enter => {
+                let _ = self.activate_action("editing-area.confirm", None);
+                return glib::Propagation::Stop;
+            }
+            // Letting go of a picked command comes before giving up.
+            gdk4::Key::Escape if self.imp().selected_command.take().is_some() => {
+                self.queue_draw();
+                return glib::Propagation::Stop;
+            }
+            gdk4::Key::Escape => {
+                let _ = self.activate_action("editing-area.cancel", None);
+                return glib::Propagation::Stop;
+            }
+            gdk4::Key::Delete | gdk4::Key::BackSpace => {
+                let Some(index) = self.imp().selected_command.take() else {
+                    return glib::Propagation::Proceed;
+                };
+                self.push_change(Change::Replace { index, command: None });
+                return glib::Propagation::Stop;
+            }
+            gdk4::Key::minus | gdk4::Key::KP_Subtract => {
+                if self.move_step(false) {
+                    return glib::Propagation::Stop;
+                }
+            }
+            gdk4::Key::plus | gdk4::Key::equal | gdk4::Key::KP_Add => {
+                if self.move_step(true) {
+                    return glib::Propagation::Stop;
+                }
+            }
+            _ => {}
+        }
+
+        let MaybeSelection::Selection(mut selection) = self.selection().0 else {
+            return glib::Propagation::Proceed;
+        };
+        let imp = self.imp();
+        let step = if modifiers.contains(gdk4::ModifierType::SHIFT_MASK) { 10.0 } else { 1.0 };
+        let (dx, dy) = match key {
+            gdk4::Key::Left => (-step, 0.0),
+            gdk4::Key::Right => (step, 0.0),
+            gdk4::Key::Up => (0.0, -step),
+            gdk4::Key::Down => (0.0, step),
+            // Shift+Tab arrives as ISO_Left_Tab.
+            gdk4::Key::Tab | gdk4::Key::ISO_Left_Tab => {
+                let handle = imp.active_handle.get();
+                let handle = if key == gdk4::Key::Tab { handle.next() } else { handle.previous() };
+                imp.active_handle.set(handle);
+                self.queue_draw();
+                return glib::Propagation::Stop;
+            }
+            _ => return glib::Propagation::Proceed,
+        };
+
+        selection.set_constraint(self.constraint());
+        if modifiers.contains(gdk4::ModifierType::CONTROL_MASK) {
+            let handle = imp.active_handle.get();
+            let (x, y) = handle.position(&selection);
+            selection.reshape_relative(handle, dx, dy);
+            imp.active_handle.set(handle.after_reshape(&selection, (x + dx, y + dy)));
+        } else {
+            selection.move_relative(dx, dy);
+        }
+        self.set_selection(MaybeSelectionBoxed::from(MaybeSelection::Selection(selection)));
+        self.queue_draw();
+        glib::Propagation::Stop
+    }
+
+    /// Sets the cursor for what a drag starting at `point` would do, and
+    /// highlights the window a click would select.
+    fn update_cursor(&self, point: (f32, f32)) {
+        let hit = match (self.tool(), self.selection().0) {
+            (Tool::Selection, selection) => self.command_hit(point).map(|(_, hit)| hit).or(match selection {
+                MaybeSelection::Selection(selection) => selection.hit_test(point).map(CommandHit::Bounds),
+                MaybeSelection::NoSelection(_) => None,
+            }),
+            _ => None,
+        };
+        let cursor = if self.tool() == Tool::Text || self.text_edit_contains(point) {
+            "text"
+        } else {
+            hit.map_or("crosshair", CommandHit::cursor_name)
+        };
+        self.set_cursor_from_name(Some(cursor));
+
+        let hovered = match (self.tool(), hit) {
+            (Tool::Selection, None) => self.rect_at(point),
+            _ => None,
+        };
+        if *self.imp().hovered_rect.borrow() != hovered {
+            self.imp().hovered_rect.replace(hovered);
+            self.queue_draw();
+        }
+    }
+
+    /// Sets the windows the selection snaps to and clicks select, in the
+    /// compositor's global space.
+    pub fn set_windows(&self, windows: Vec<WindowGeometry>) {
+        self.imp().windows.replace(windows);
+    }
+
+    /// Window and output rectangles in widget coordinates, topmost last.
+    fn snap_rects(&self) -> Vec<graphene::Rect> {
+        let origin = (self.origin_x() as f32, self.origin_y() as f32);
+        let rect = |x: i32, y: i32, width: i32, height: i32| {
+            graphene::Rect::new(
+                x as f32 - origin.0,
+                y as f32 - origin.1,
+                width as f32,
+                height as f32,
+            )
+        };
+        let capture = self.capture();
+        let outputs = capture
+            .iter()
+            .map(|output| rect(output.x, output.y, output.width, output.height));
+        let windows = self.imp().windows.borrow();
+        let windows = windows
+            .iter()
+            .map(|window| rect(window.x, window.y, window.width, window.height));
+        outputs.chain(windows).collect()
+    }
+
+    fn snap_targets(&self) -> SnapTargets {
+        SnapTargets::from_rects(&self.snap_rects())
+    }
+
+    /// The topmost window under `point`, or the output if there is none.
+    fn rect_at(&self, point: (f32, f32)) -> Option<graphene::Rect> {
+        let point = graphene::Point::new(point.0, point.1);
+        self.snap_rects()
+            .into_iter()
+            .rev()
+            .find(|rect| rect.contains_point(&point))
+    }
+
+    /// Selects the window or output under `point`.
+    fn select_rect_at(&self, point: (f32, f32)) {
+        let Some(rect) = self.rect_at(point) else {
+            return;
+        };
+        let selection = Selection::new_from_size((rect.x(), rect.y()), rect.width(), rect.height());
+        self.set_selection(MaybeSelectionBoxed::from(MaybeSelection::Selection(selection)));
+        self.queue_draw();
+    }
+
+    /// Keeps `other`, an editing area on another output, showing the same
+    /// selection, commands and tool as this one.
+    ///
+    /// Coordinates are relative to each widget, so they are translated by
+    /// the difference between the origins on the way, which must be set
+    /// beforehand.
+    pub fn share_with(&self, other: &EditingArea) {
+        let dx = (self.origin_x() - other.origin_x()) as f32;
+        let dy = (self.origin_y() - other.origin_y()) as f32;
+
+        self.bind_property("selection", other, "selection")
+            .transform_to(move |_, selection: MaybeSelectionBoxed| {
+                Some(MaybeSelectionBoxed::from(selection.0.translated(dx, dy)))
+            })
+            .transform_from(move |_, selection: MaybeSelectionBoxed| {
+                Some(MaybeSelectionBoxed::from(selection.0.translated(-dx, -dy)))
+            })
+            .bidirectional()
+            .sync_create()
+            .build();
+        for stack in ["undo-stack", "redo-stack"] {
+            self.bind_property(stack, other, stack)
+                .transform_to(move |_, commands: CommandsBoxed| Some(commands.translated(dx, dy)))
+                .transform_from(move |_, commands: CommandsBoxed| {
+                    Some(commands.translated(-dx, -dy))
+                })
+                .bidirectional()
+                .sync_create()
+                .build();
+        }
+        for property in ["constraint", "tool", "tool-style"] {
+            self.bind_property(property, other, property)
+                .bidirectional()
+                .sync_create()
+                .build();
+        }
+    }
+
+    /// Adds a command on top of the others. Whatever was undone before is
+    /// gone for good.
+    pub fn push_command(&self, command: Command) {
+        self.push_change(Change::Add(command));
+    }
+
+    /// Records a change on the undo stack. Whatever was undone before is
+    /// gone for good.
+    pub fn push_change(&self, change: Change) {
+        let imp = self.imp();
+        imp.undo_stack.borrow_mut().push(change);
+        imp.redo_stack.borrow_mut().clear();
+        imp.stacks_changed();
+    }
+
+    /// Takes back the last change. Returns whether there was one.
+    pub fn undo(&self) -> bool {
+        // Text being typed is a change of its own to take back.
+        self.end_text_edit();
+        let imp = self.imp();
+        let Some(change) = imp.undo_stack.borrow_mut().pop() else {
+            return false;
+        };
+        imp.redo_stack.borrow_mut().push(change);
+        imp.stacks_changed();
+        true
+    }
+
+    /// Brings back the last undone change. Returns whether there was one.
+    pub fn redo(&self) -> bool {
+        self.end_text_edit();
+        let imp = self.imp();
+        let Some(change) = imp.redo_stack.borrow_mut().pop() else {
+            return false;
+        };
+        imp.undo_stack.borrow_mut().push(change);
+        imp.stacks_changed();
+        true
+    }
+
+    /// The bounds of the picked command, where its handles are drawn.
+    fn selected_command_bounds(&self) -> Option<Selection> {
+        let imp = self.imp();
+        if let Some(edit) = imp.command_edit.borrow().as_ref() {
+            return Some(edit.bounds);
+        }
+        let index = imp.selected_command.get()?;
+        let bounds = imp.undo_stack.borrow().command(index)?.bounds(self.clone());
+        Some(Selection::new_from_size((bounds.x(), bounds.y()), bounds.width(), bounds.height()))
+    }
+
+    /// Where the picked command's tail points, if it is a callout, where
+    /// its knob is drawn.
+    fn selected_command_anchor(&self) -> Option<(f32, f32)> {
+        let imp = self.imp();
+        if let Some(edit) = imp.command_edit.borrow().as_ref() {
+            return edit.command.anchor();
+        }
+        let index = imp.selected_command.get()?;
+        imp.undo_stack.borrow().command(index)?.anchor()
+    }
+
+    /// Swaps the picked step marker's number with the next (`later`) or
+    /// previous marker's, by moving it to that marker's place. Returns
+    /// whether there was such a marker.
+    fn move_step(&self, later: bool) -> bool {
+        let imp = self.imp();
+        let Some(index) = imp.selected_command.get() else {
+            return false;
+        };
+        let undo_stack = imp.undo_stack.borrow();
+        let markers: Vec<usize> = undo_stack
+            .resolve()
+            .into_iter()
+            .filter(|(_, command)| matches!(command.command_type, CommandType::StepMarker { .. }))
+            .map(|(added, _)| added)
+            .collect();
+        drop(undo_stack);
+        let Some(position) = markers.iter().position(|added| *added == index) else {
+            return false;
+        };
+        let other = if later { markers.get(position + 1) } else { position.checked_sub(1).map(|p| &markers[p]) };
+        let Some(&to) = other else {
+            return false;
+        };
+        self.push_change(Change::Move { index, to });
+        true
+    }
+
+    /// What a drag starting at `point` would grab: the picked callout's
+    /// anchor, a handle or the inside of the picked command's bounds, or the
+    /// topmost command under it, along with the index of the change that
+    /// added it.
+    fn command_hit(&self, point: (f32, f32)) -> Option<(usize, CommandHit)> {
+        let imp = self.imp();
+        if let Some(index) = imp.selected_command.get() {
+            if self.selected_command_anchor().is_some_and(|anchor| selection::near_knob(anchor, point)) {
+                return Some((index, CommandHit::Anchor));
+            }
+            if let Some(hit) = self.selected_command_bounds().and_then(|bounds| bounds.hit_test(point)) {
+                return Some((index, CommandHit::Bounds(hit)));
+            }
+        }
+        let undo_stack = imp.undo_stack.borrow();
+        let commands = undo_stack.resolve();
+        let (index, _) = commands
+            .iter()
+            .rev()
+            .find(|(_, command)| command.hit_test(point, self.clone()))?;
+        Some((*index, CommandHit::Bounds(SelectionHit::Interior)))
+    }
+
+    /// Picks the command under `point` and starts moving or resizing it.
+    /// Returns false, letting go of the picked command, when there is
+    /// nothing there.
+    fn begin_command_edit(&self, point: (f32, f32)) -> bool {
+        let imp = self.imp();
+        let Some((index, hit)) = self.command_hit(point) else {
+            imp.selected_command.set(None);
+            return false;
+        };
+        let Some(command) = imp.undo_stack.borrow().command(index).cloned() else {
+            return false;
+        };
+        let bounds = command.bounds(self.clone());
+        imp.selected_command.set(Some(index));
+        imp.command_edit.replace(Some(CommandEdit::new(index, hit, command, bounds)));
+        true
+    }
+
+    /// Starts typing into a text `command`, new when `index` is `None`,
+    /// otherwise the one added by the change at `index`. Whatever was being
+    /// typed before is recorded first.
+    fn begin_text_edit(&self, index: Option<usize>, command: Command) {
+        self.end_text_edit();
+        let imp = self.imp();
+        imp.command_edit.take();
+        imp.selected_command.set(None);
+        imp.text_edit.replace(Some(TextEdit::new(index, command)));
+        imp.im_context.focus_in();
+        self.grab_focus();
+        self.text_edit_changed();
+    }
+
+    /// Stops typing and records the text as a single change: added when it
+    /// is new, replaced when it was reopened, removed when it was emptied.
+    pub fn end_text_edit(&self) {
+        let imp = self.imp();
+        let Some(edit) = imp.text_edit.take() else {
+            return;
+        };
+        imp.im_context.focus_out();
+        imp.im_context.reset();
+        let (index, original) = (edit.index, edit.original.clone());
+        match (index, edit.finished()) {
+            (None, Some(command)) => self.push_command(command),
+            (Some(index), command) if command != original => {
+                self.push_change(Change::Replace { index, command })
+            }
+            _ => self.queue_draw(),
+        }
+    }
+
+    /// Double-clicking reopens the topmost text command or callout under
+    /// `point`.
+    fn reopen_text_at(&self, point: (f32, f32)) {
+        // Unless it is the empty text the first click placed with the text
+        // tool, what is being typed is already open.
+        let typing_here = self.imp().text_edit.borrow().as_ref().is_some_and(|edit| {
+            (edit.index.is_some() || !edit.text().is_empty()) && edit.contains(point, self)
+        });
+        if typing_here {
+            return;
+        }
+        // Recorded before looking, it may be the text under the pointer.
+        self.end_text_edit();
+        let undo_stack = self.imp().undo_stack.borrow();
+        let Some((index, command)) = undo_stack.resolve().into_iter().rev().find(|(_, command)| {
+            command.has_text() && command.hit_test(point, self.clone())
+        }) else {
+            return;
+        };
+        let command = command.clone();
+        drop(undo_stack);
+        self.begin_text_edit(Some(index), command);
+        self.move_text_caret(point, false);
+    }
+
+    fn text_edit_contains(&self, point: (f32, f32)) -> bool {
+        self.imp()
+            .text_edit
+            .borrow()
+            .as_ref()
+            .is_some_and(|edit| edit.contains(point, self))
+    }
+
+    /// Moves the caret of the text being typed to the character closest to
+    /// `point`, selecting what is in between when `extend`.
+    fn move_text_caret(&self, point: (f32, f32), extend: bool) {
+        if let Some(edit) = self.imp().text_edit.borrow_mut().as_mut() {
+            let layout = edit.layout(self);
+            let start = edit.command.start;
+            let index = edit.index_at(&layout, (point.0 - start.0, point.1 - start.1));
+            edit.move_to(index, extend);
+        }
+        self.text_edit_changed();
+    }
+
+    fn insert_text(&self, text: &str) {
+        if let Some(edit) = self.imp().text_edit.borrow_mut().as_mut() {
+            edit.insert(text);
+        }
+        self.text_edit_changed();
+    }
+
+    /// Inserts the text on the clipboard where the caret is, once it has
+    /// been read.
+    fn paste_text(&self) {
+        let editing_area = self.downgrade();
+        self.clipboard().read_text_async(None::<&gio::Cancellable>, move |result| {
+            let Some(editing_area) = editing_area.upgrade() else {
+                return;
+            };
+            match result {
+                Ok(Some(text)) => editing_area.insert_text(&text),
+                Ok(None) => {}
+                Err(error) => eprintln!("Could not paste: {}", error),
+            }
+        });
+    }
+
+    /// Tells the input method where the caret went and redraws.
+    fn text_edit_changed(&self) {
+        if let Some(edit) = self.imp().text_edit.borrow().as_ref() {
+            let caret = edit.caret_rect(self);
+            self.imp().im_context.set_cursor_location(&gdk4::Rectangle::new(
+                caret.x() as i32,
+                caret.y() as i32,
+                caret.width().ceil() as i32,
+                caret.height().ceil() as i32,
+            ));
+        }
+        self.queue_draw();
+    }
+
+    /// Editing keys for the text being typed, after the input method had
+    /// its chance. Enter starts a new line and Escape is done typing.
+    /// Ctrl+B and Ctrl+I embolden and italicize the selected text, Ctrl+L,
+    /// Ctrl+E and Ctrl+R align it, Ctrl+Up and Ctrl+Down change the line
+    /// spacing, Ctrl+H toggles a halo and Ctrl+Shift+B a box behind it.
+    /// Other keys with Ctrl are left to the shortcuts.
+    fn handle_text_key(
+        &self,
+        controller: &gtk4::EventControllerKey,
+        key: gdk4::Key,
+        modifiers: gdk4::ModifierType,
+    ) -> glib::Propagation {
+        let imp = self.imp();
+        if imp.text_edit.borrow().is_none() {
+            return glib::Propagation::Proceed;
+        }
+        if let Some(event) = controller.current_event() {
+            if imp.im_context.filter_keypress(event) {
+                return glib::Propagation::Stop;
+            }
+        }
+
+        let control = modifiers.contains(gdk4::ModifierType::CONTROL_MASK);
+        let extend = modifiers.contains(gdk4::ModifierType::SHIFT_MASK);
+        match key {
+            gdk4::Key::Escape => {
+                self.end_text_edit();
+                return glib::Propagation::Stop;
+            }
+            gdk4::Key::v if control => {
+                self.paste_text();
+                return glib::Propagation::Stop;
+            }
+            _ => {}
+        }
+
+        let mut text_edit = imp.text_edit.borrow_mut();
+        let Some(edit) = text_edit.as_mut() else {
+            return glib::Propagation::Proceed;
+        };
+        let layout = edit.layout(self);
+        let selection = edit.selection();
+        // Halos and boxes stand out from the text's color.
+        let color = edit.command.fill_color.unwrap_or(edit.command.color);
+        match key {
+            gdk4::Key::Return | gdk4::Key::KP_Enter => edit.insert("\n"),
+            gdk4::Key::BackSpace => edit.delete_backward(),
+            gdk4::Key::Delete | gdk4::Key::KP_Delete => edit.delete_forward(),
+            // Without Shift, the caret goes to the side of the selection.
+            gdk4::Key::Left if !extend && !selection.is_empty() => edit.move_to(selection.start, false),
+            gdk4::Key::Right if !extend && !selection.is_empty() => edit.move_to(selection.end, false),
+            gdk4::Key::Left => edit.move_to(edit.previous_boundary(edit.cursor), extend),
+            gdk4::Key::Right => edit.move_to(edit.next_boundary(edit.cursor), extend),
+            gdk4::Key::Up | gdk4::Key::Down if control => {
+                let style = edit.style_mut();
+                // 0 is the font's own spacing, about one line.
+                let spacing = if style.line_spacing == 0.0 { 1.0 } else { style.line_spacing };
+                let step = if key == gdk4::Key::Up { 0.1 } else { -0.1 };
+                style.line_spacing = (spacing + step).clamp(0.5, 3.0);
+            }
+            gdk4::Key::Up => edit.move_to(edit.line_boundary(&layout, -1), extend),
+            gdk4::Key::Down => edit.move_to(edit.line_boundary(&layout, 1), extend),
+            gdk4::Key::Home => edit.move_to(edit.line_end(&layout, false), extend),
+            gdk4::Key::End => edit.move_to(edit.line_end(&layout, true), extend),
+            gdk4::Key::a if control => edit.select_all(),
+            gdk4::Key::b if control => edit.toggle_emphasis(Emphasis::Bold),
+            gdk4::Key::i if control => edit.toggle_emphasis(Emphasis::Italic),
+            gdk4::Key::l if control => edit.style_mut().alignment = pango::Alignment::Left,
+            gdk4::Key::e if control => edit.style_mut().alignment = pango::Alignment::Center,
+            gdk4::Key::r if control => edit.style_mut().alignment = pango::Alignment::Right,
+            // Ctrl+Shift+B arrives as a capital B.
+            gdk4::Key::B if control => edit.style_mut().toggle_background(color),
+            gdk4::Key::h if control => edit.style_mut().toggle_halo(color),
+            _ if control => return glib::Propagation::Proceed,
+            // Nothing else types anything, or switches tools while typing.
+            _ => return glib::Propagation::Stop,
+        }
+        drop(text_edit);
+        self.text_edit_changed();
+        glib::Propagation::Stop
+    }
+
+    pub fn connect_confirmed<F: Fn(&Self) + 'static>(&self, f: F) -> glib::SignalHandlerId {
+        self.connect_local("confirmed", false, move |values| {
+            let editing_area = values[0].get::<Self>().expect("signal emitted by an EditingArea");
+            f(&editing_area);
+            None
+        })
+    }
+
+    pub fn connect_cancelled<F: Fn(&Self) + 'static>(&self, f: F) -> glib::SignalHandlerId {
+        self.connect_local("cancelled", false, move |values| {
+            let editing_area = values[0].get::<Self>().expect("signal emitted by an EditingArea");
+            f(&editing_area);
+            None
+        })
+    }
+
+    /// Renders the selected region with its annotations, at the highest
+    /// resolution of the captured outputs.
+    pub fn render(&self) -> Result<cairo::ImageSurface, RenderError> {
+        let MaybeSelection::Selection(selection) = self.selection().0 else {
+            return Err(RenderError::EmptySelection);
+        };
+        let capture = self.capture();
+        let scale = match self.export_scale() {
+            scale if scale > 0.0 => scale,
+            _ => capture.max_scale(),
+        };
+        render::render_selection(
+            &capture,
+            (self.origin_x() as f32, self.origin_y() as f32),
+            &self.undo_stack(),
+            &selection,
+            scale,
+        )
+    }
+
+    /// Renders the selected region and writes it to `path`.
+    pub fn export_to_file(&self, path: &Path, options: &ExportOptions) -> Result<(), ExportError> {
+        let surface = self.render()?;
+        export::export_to_file(&surface, path, options)
+    }
+
+    /// Renders the selected region and offers it on the clipboard as a PNG.
+    pub fn copy_to_clipboard(&self) -> Result<(), ExportError> {
+        let surface = self.render()?;
+        let pixels = Pixels::from_surface(&surface)?;
+        let png = export::encode(&pixels, ExportFormat::Png, export::DEFAULT_QUALITY)?;
+        let png = glib::Bytes::from_owned(png);
+        clipboard::set_png(&self.clipboard(), &png)
+            .map_err(|error| ExportError::Clipboard(error.to_string()))?;
+        self.imp().copied.replace(Some(png));
+        Ok(())
+    }
+
+    /// Makes the copied image outlive us, if it is still on the clipboard.
+    /// Meant to be called right before exiting.
+    pub fn persist_clipboard(&self) {
+        if let Some(png) = self.imp().copied.borrow().as_ref() {
+            clipboard::persist_if_owned(&self.clipboard(), png);
+        }
+    }
+
+    /// Saves the selection in the pictures directory, named after the
+    /// current time. Without a format in `options` it is saved as PNG.
+    pub fn save_to_pictures(&self, options: &ExportOptions) -> Result<PathBuf, ExportError> {
+        let format = options.format.unwrap_or(ExportFormat::Png);
+        let directory = glib::user_special_dir(glib::UserDirectory::Pictures)
+            .unwrap_or_else(glib::home_dir);
+        let name = glib::DateTime::now_local()
+            .and_then(|now| now.format("Screenshot_%Y-%m-%d_%H-%M-%S"))
+            .map(String::from)
+            .unwrap_or_else(|_| "Screenshot".to_string());
+        let path = directory.join(format!("{}.{}", name, format.extension()));
+        self.export_to_file(&path, &ExportOptions { format: Some(format), ..*options })?;
+        Ok(path)
+    }
 }
@@ -0,0 +1,243 @@
+//! Blurring and pixelating what is under a command, to hide secrets.
+//!
+//! Both effects work on the actual pixels: in the exported image the
+//! region is replaced by the processed copy, so nothing of the original
+//! is left underneath to be recovered.
+
+use gsk4::prelude::GskRendererExt;
+use gtk4::prelude::{NativeExt, SnapshotExt, TextureExt, WidgetExt};
+
+use glib::object::IsA;
+
+/// How a region is hidden.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Redaction {
+    /// Gaussian-like blur, `radius` in pixels like gsk's blur node.
+    Blur { radius: f32 },
+    /// Squares of `block_size` pixels filled with their average color.
+    Pixelate { block_size: f32 },
+    /// Opaque black, nothing of what was there is left.
+    Fill,
+}
+
+impl Redaction {
+    /// Applies the redaction to premultiplied ARGB32 pixels, with `scale`
+    /// pixels per logical pixel, the unit the sizes are in.
+    fn apply(self, data: &mut [u8], width: usize, height: usize, stride: usize, scale: f64) {
+        match self {
+            Redaction::Blur { radius } => {
+                let sigma = radius as f64 * scale / 2.0;
+                blur(data, width, height, stride, sigma);
+            }
+            Redaction::Pixelate { block_size } => {
+                let block = (block_size as f64 * scale).round().max(1.0) as usize;
+                pixelate(data, width, height, stride, block);
+            }
+            Redaction::Fill => {
+                let black = 0xFF000000u32.to_ne_bytes();
+                for row in data.chunks_mut(stride).take(height) {
+                    for pixel in row[..width * 4].chunks_exact_mut(4) {
+                        pixel.copy_from_slice(&black);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Draws `below` redacted inside `rect`, both in widget coordinates.
+    /// Only filling works without knowing what is below.
+    pub fn draw_to_snapshot(
+        self,
+        snapshot: &gtk4::Snapshot,
+        widget: impl IsA<gtk4::Widget>,
+        below: Option<&gsk4::RenderNode>,
+        rect: &graphene::Rect,
+    ) {
+        snapshot.push_clip(rect);
+        match (self, below) {
+            (Redaction::Blur { radius }, Some(below)) => {
+                snapshot.push_blur(radius as f64);
+                snapshot.append_node(below);
+                snapshot.pop();
+            }
+            (Redaction::Pixelate { .. }, Some(below)) => {
+                if let Some(texture) = self.mosaic_texture(widget, below, rect) {
+                    snapshot.append_scaled_texture(&texture, gsk4::ScalingFilter::Nearest, rect);
+                }
+            }
+            (Redaction::Fill, _) => snapshot.append_color(&gdk4::RGBA::BLACK, rect),
+            (_, None) => {}
+        }
+        snapshot.pop();
+    }
+
+    /// Renders `below` inside `rect` to a texture with one pixel per logical
+    /// pixel and pixelates it the way the exported image will be.
+    fn mosaic_texture(
+        self,
+        widget: impl IsA<gtk4::Widget>,
+        below: &gsk4::RenderNode,
+        rect: &graphene::Rect,
+    ) -> Option<gdk4::Texture> {
+        let renderer = widget.native()?.renderer()?;
+        let texture = renderer.render_texture(below, Some(rect));
+        let width = texture.width() as usize;
+        let height = texture.height() as usize;
+        if width == 0 || height == 0 {
+            return None;
+        }
+        let stride = width * 4;
+        // gdk_texture_download produces cairo's native ARGB32 layout.
+        let mut data = vec![0u8; stride * height];
+        texture.download(&mut data, stride);
+        self.apply(&mut data, width, height, stride, 1.0);
+
+        #[cfg(target_endian = "little")]
+        let format = gdk4::MemoryFormat::B8g8r8a8Premultiplied;
+        #[cfg(target_endian = "big")]
+        let format = gdk4::MemoryFormat::A8r8g8b8Premultiplied;
+        let bytes = glib::Bytes::from_owned(data);
+        Some(gdk4::MemoryTexture::new(width as i32, height as i32, format, &bytes, stride).into())
+    }
+
+    /// Replaces the pixels of `cr`'s target inside `rect`, in user space,
+    /// with their redacted version.
+    ///
+    /// Everything drawn so far is affected, so this has to be called in
+    /// the order the commands are stacked. The target must be an image
+    /// surface, which the offscreen renderer uses.
+    pub fn apply_to_cairo(self, cr: &cairo::Context, rect: &graphene::Rect) -> Result<(), cairo::Error> {
+        let surface = cairo::ImageSurface::try_from(cr.target())
+            .map_err(|_| cairo::Error::SurfaceTypeMismatch)?;
+        let (x0, y0) = cr.user_to_device(rect.x() as f64, rect.y() as f64);
+        let (x1, y1) = cr.user_to_device(
+            (rect.x() + rect.width()) as f64,
+            (rect.y() + rect.height()) as f64,
+        );
+        // Whole pixels, same rounding as the edges of the exported selection.
+        let to_pixel = |device: f64, size: i32| (device.round() as i32).clamp(0, size);
+        let (left, right) = (to_pixel(x0.min(x1), surface.width()), to_pixel(x0.max(x1), surface.width()));
+        let (top, bottom) = (to_pixel(y0.min(y1), surface.height()), to_pixel(y0.max(y1), surface.height()));
+        let (width, height) = ((right - left) as usize, (bottom - top) as usize);
+        if width == 0 || height == 0 {
+            return Ok(());
+        }
+        // How many device pixels a logical one is, for the sizes.
+        let scale = cr.user_to_device_distance(1.0, 0.0)?.0.abs();
+
+        let stride = width * 4;
+        let mut region = vec![0u8; stride * height];
+        let source_stride = surface.stride() as usize;
+        surface
+            .with_data(|data| {
+                for row in 0..height {
+                    let start = (top as usize + row) * source_stride + left as usize * 4;
+                    region[row * stride..(row + 1) * stride].copy_from_slice(&data[start..start + stride]);
+                }
+            })
+            .map_err(|_| cairo::Error::InvalidStatus)?;
+        self.apply(&mut region, width, height, stride, scale);
+
+        let redacted = cairo::ImageSurface::create_for_data(
+            region,
+            cairo::Format::ARgb32,
+            width as i32,
+            height as i32,
+            stride as i32,
+        )?;
+        // In device space, with SOURCE so the pixels are replaced and not
+        // blended with what was there.
+        cr.save()?;
+        cr.identity_matrix();
+        cr.set_operator(cairo::Operator::Source);
+        cr.set_source_surface(&redacted, left as f64, top as f64)?;
+        cr.rectangle(left as f64, top as f64, width as f64, height as f64);
+        cr.fill()?;
+        cr.restore()
+    }
+}
+
+/// Approximates a gaussian blur of standard deviation `sigma` with three
+/// box blurs, horizontally then vertically. Edges are clamped.
+fn blur(data: &mut [u8], width: usize, height: usize, stride: usize, sigma: f64) {
+    // Box width giving that deviation over three passes.
+    let box_width = (4.0 * sigma * sigma + 1.0).sqrt();
+    let box_radius = ((box_width - 1.0) / 2.0).round() as usize;
+    if box_radius == 0 {
+        return;
+    }
+    let mut line = Vec::new();
+    for _ in 0..3 {
+        for y in 0..height {
+            box_blur_line(data, y * stride, 4, width, box_radius, &mut line);
+        }
+        for x in 0..width {
+            box_blur_line(data, x * 4, stride, height, box_radius, &mut line);
+        }
+    }
+}
+
+/// Box blurs the `length` pixels starting at `offset`, `step` bytes apart.
+fn box_blur_line(
+    data: &mut [u8],
+    offset: usize,
+    step: usize,
+    length: usize,
+    radius: usize,
+    line: &mut Vec<[u8; 4]>,
+) {
+    line.clear();
+    line.extend((0..length).map(|i| {
+        let pixel = offset + i * step;
+        [data[pixel], data[pixel + 1], data[pixel + 2], data[pixel + 3]]
+    }));
+    let at = |i: isize| line[i.clamp(0, length as isize - 1) as usize];
+    let window = (2 * radius + 1) as u32;
+
+    let mut sums = [0u32; 4];
+    for i in -(radius as isize)..=radius as isize {
+        for (sum, value) in sums.iter_mut().zip(at(i)) {
+            *sum += value as u32;
+        }
+    }
+    for i in 0..length {
+        let pixel = offset + i * step;
+        for (value, sum) in data[pixel..pixel + 4].iter_mut().zip(sums) {
+            *value = ((sum + window / 2) / window) as u8;
+        }
+        let leaving = at(i as isize - radius as isize);
+        let entering = at(i as isize + radius as isize + 1);
+        for ((sum, entering), leaving) in sums.iter_mut().zip(entering).zip(leaving) {
+            *sum = *sum + entering as u32 - leaving as u32;
+        }
+    }
+}
+
+/// Fills every `block` by `block` square, starting from the top-left
+/// corner, with the average of its pixels.
+fn pixelate(data: &mut [u8], width: usize, height: usize, stride: usize, block: usize) {
+    for block_y in (0..height).step_by(block) {
+        for block_x in (0..width).step_by(block) {
+            let rows = block_y..(block_y + block).min(height);
+            let columns = block_x..(block_x + block).min(width);
+            let count = (rows.len() * columns.len()) as u32;
+
+            let mut sums = [0u32; 4];
+            for y in rows.clone() {
+                for x in columns.clone() {
+                    let pixel = y * stride + x * 4;
+                    for (sum, value) in sums.iter_mut().zip(&data[pixel..pixel + 4]) {
+                        *sum += *value as u32;
+                    }
+                }
+            }
+            let average = sums.map(|sum| ((sum + count / 2) / count) as u8);
+            for y in rows {
+                for x in columns.clone() {
+                    let pixel = y * stride + x * 4;
+                    data[pixel..pixel + 4].copy_from_slice(&average);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,286 @@
+//! How text commands look beyond their font: alignment, line spacing, bold
+//! and italic runs, and the halo and box that keep them readable.
+
+use std::ops::Range;
+
+use super::command::contrasting;
+
+/// Room between the text's ink and the edge of its background box.
+pub const BOX_PADDING: f32 = 6.0;
+pub const BOX_RADIUS: f32 = 6.0;
+/// How far the halo reaches past the text's own stroke, on each side.
+pub const HALO_WIDTH: f32 = 3.0;
+
+/// How a text command is laid out and decorated.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TextStyle {
+    pub font: String,
+    /// How lines line up with each other.
+    pub alignment: pango::Alignment,
+    /// Distance between baselines as a factor of the font's line height, 0
+    /// for the font's own spacing.
+    pub line_spacing: f32,
+    /// Color of a wide stroke drawn under the text.
+    pub halo: Option<u32>,
+    /// Color of a rounded box drawn under the text's ink.
+    pub background: Option<u32>,
+}
+
+impl Default for TextStyle {
+    fn default() -> Self {
+        TextStyle {
+            font: "Sans 16".to_string(),
+            alignment: pango::Alignment::Left,
+            line_spacing: 0.0,
+            halo: None,
+            background: None,
+        }
+    }
+}
+
+impl TextStyle {
+    /// Toggles a halo that stands out from text in `color`.
+    pub fn toggle_halo(&mut self, color: u32) {
+        self.halo = match self.halo {
+            Some(_) => None,
+            None => Some(contrasting(color)),
+        };
+    }
+
+    /// Toggles a slightly translucent box that stands out from text in `color`.
+    pub fn toggle_background(&mut self, color: u32) {
+        self.background = match self.background {
+            Some(_) => None,
+            None => Some((contrasting(color) & 0xFFFFFF00) | 0xE6),
+        };
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Emphasis {
+    Bold,
+    Italic,
+}
+
+/// Part of a text, as a byte range, shown with some emphasis. Runs may
+/// overlap.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TextRun {
+    pub range: Range<usize>,
+    pub emphasis: Emphasis,
+}
+
+/// `text` as Pango markup, with the emphasis of `runs`.
+pub fn markup(text: &str, runs: &[TextRun]) -> String {
+    let mut boundaries: Vec<usize> = runs
+        .iter()
+        .flat_map(|run| [run.range.start, run.range.end])
+        .chain([0, text.len()])
+        .filter(|boundary| *boundary <= text.len())
+        .collect();
+    boundaries.sort_unstable();
+    boundaries.dedup();
+
+    let mut markup = String::new();
+    for segment in boundaries.windows(2) {
+        let (start, end) = (segment[0], segment[1]);
+        let has = |emphasis| {
+            runs.iter()
+                .any(|run| run.emphasis == emphasis && run.range.start <= start && end <= run.range.end)
+        };
+        let (bold, italic) = (has(Emphasis::Bold), has(Emphasis::Italic));
+        markup.push_str(match (bold, italic) {
+            (true, true) => "<b><i>",
+            (true, false) => "<b>",
+            (false, true) => "<i>",
+            (false, false) => "",
+        });
+        markup.push_str(&glib::markup_escape_text(&text[start..end]));
+        markup.push_str(match (bold, italic) {
+            (true, true) => "</i></b>",
+            (true, false) => "</b>",
+            (false, true) => "</i>",
+            (false, false) => "",
+        });
+    }
+    markup
+}
+
+/// Keeps `runs` on the same characters once `replaced` was replaced by
+/// `inserted` bytes of text. Text typed at the end of a run joins it, text
+/// typed at its start does not.
+pub fn replace(runs: &mut Vec<TextRun>, replaced: Range<usize>, inserted: usize) {
+    let after = |position: usize| position - replaced.len() + inserted;
+    for run in runs.iter_mut() {
+        let start = match run.range.start {
+            start if start < replaced.start => start,
+            start if start >= replaced.end => after(start),
+            _ => replaced.start + inserted,
+        };
+        let end = match run.range.end {
+            end if end >= replaced.end => after(end),
+            end if end <= replaced.start => end,
+            _ => replaced.start,
+        };
+        run.range = start..end;
+    }
+    runs.retain(|run| !run.range.is_empty());
+}
+
+/// Turns `emphasis` off over `range` if all of it has it, on otherwise.
+pub fn toggle(runs: &mut Vec<TextRun>, range: Range<usize>, emphasis: Emphasis) {
+    if range.is_empty() {
+        return;
+    }
+    let mut covered = range.start;
+    let mut starts: Vec<&TextRun> = runs.iter().filter(|run| run.emphasis == emphasis).collect();
+    starts.sort_by_key(|run| run.range.start);
+    for run in starts {
+        if run.range.start <= covered {
+            covered = covered.max(run.range.end);
+        }
+    }
+
+    if covered < range.end {
+        runs.push(TextRun { range, emphasis });
+        return;
+    }
+    // Cut the range out of every run with that emphasis.
+    let mut kept = Vec::new();
+    for run in runs.drain(..) {
+        if run.emphasis != emphasis || run.range.end <= range.start || run.range.start >= range.end {
+            kept.push(run);
+            continue;
+        }
+        for part in [run.range.start..range.start, range.end..run.range.end] {
+            if !part.is_empty() {
+                kept.push(TextRun { range: part, emphasis });
+            }
+        }
+    }
+    *runs = kept;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(range: Range<usize>, emphasis: Emphasis) -> TextRun {
+        TextRun { range, emphasis }
+    }
+
+    fn bold(range: Range<usize>) -> TextRun {
+        run(range, Emphasis::Bold)
+    }
+
+    /// Which of the first `length` bytes have `emphasis`, as `x` or `.`.
+    fn mask(runs: &[TextRun], emphasis: Emphasis, length: usize) -> String {
+        (0..length)
+            .map(|index| {
+                let has = runs.iter().any(|run| run.emphasis == emphasis && run.range.contains(&index));
+                if has { 'x' } else { '.' }
+            })
+            .collect()
+    }
+
+    fn replaced(runs: &[TextRun], range: Range<usize>, inserted: usize) -> Vec<TextRun> {
+        let mut runs = runs.to_vec();
+        replace(&mut runs, range, inserted);
+        runs
+    }
+
+    fn toggled(runs: &[TextRun], range: Range<usize>, emphasis: Emphasis) -> Vec<TextRun> {
+        let mut runs = runs.to_vec();
+        toggle(&mut runs, range, emphasis);
+        runs
+    }
+
+    #[test]
+    fn typing_inside_or_at_the_end_of_a_run_grows_it() {
+        assert_eq!(replaced(&[bold(2..6)], 4..4, 3), [bold(2..9)]);
+        assert_eq!(replaced(&[bold(2..6)], 6..6, 2), [bold(2..8)]);
+    }
+
+    #[test]
+    fn typing_at_the_start_of_a_run_moves_it() {
+        assert_eq!(replaced(&[bold(2..6)], 2..2, 2), [bold(4..8)]);
+        assert_eq!(replaced(&[bold(10..12)], 2..4, 1), [bold(9..11)]);
+    }
+
+    #[test]
+    fn deleting_across_a_run_boundary_shrinks_it() {
+        // Over its start, what is left of it moves to where the deletion was.
+        assert_eq!(replaced(&[bold(4..8)], 2..6, 0), [bold(2..4)]);
+        // Over its end.
+        assert_eq!(replaced(&[bold(2..6)], 4..8, 0), [bold(2..4)]);
+        // Typed over, the new text does not join it.
+        assert_eq!(replaced(&[bold(2..6)], 4..8, 5), [bold(2..4)]);
+    }
+
+    #[test]
+    fn deleting_a_whole_run_drops_it() {
+        assert_eq!(replaced(&[bold(3..5), bold(8..9)], 2..6, 0), [bold(4..5)]);
+        assert!(replaced(&[bold(3..5)], 3..5, 0).is_empty());
+    }
+
+    #[test]
+    fn runs_follow_multibyte_edits() {
+        let mut text = "héllo".to_string();
+        // "éll", é being two bytes.
+        let mut runs = vec![bold(1..5)];
+        text.replace_range(1..3, "");
+        replace(&mut runs, 1..3, 0);
+        assert_eq!(&text[runs[0].range.clone()], "ll");
+
+        text.insert_str(1, "ëë");
+        replace(&mut runs, 1..1, "ëë".len());
+        assert_eq!(&text[runs[0].range.clone()], "ll");
+    }
+
+    #[test]
+    fn toggling_plain_text_adds_a_run_and_toggling_again_removes_it() {
+        let runs = toggled(&[], 2..5, Emphasis::Bold);
+        assert_eq!(runs, [bold(2..5)]);
+        assert!(toggled(&runs, 2..5, Emphasis::Bold).is_empty());
+    }
+
+    #[test]
+    fn toggling_partly_emphasized_text_emphasizes_all_of_it() {
+        let runs = toggled(&[bold(0..4)], 2..6, Emphasis::Bold);
+        assert_eq!(mask(&runs, Emphasis::Bold, 8), "xxxxxx..");
+        // A gap between runs is not covered either.
+        let runs = toggled(&[bold(0..2), bold(3..6)], 1..5, Emphasis::Bold);
+        assert_eq!(mask(&runs, Emphasis::Bold, 6), "xxxxxx");
+    }
+
+    #[test]
+    fn toggling_across_runs_that_cover_it_cuts_it_out_of_each() {
+        let runs = toggled(&[bold(0..3), bold(3..6)], 1..5, Emphasis::Bold);
+        assert_eq!(runs, [bold(0..1), bold(5..6)]);
+        // Overlapping runs, from toggling partly emphasized text.
+        assert!(toggled(&[bold(0..4), bold(2..6)], 0..6, Emphasis::Bold).is_empty());
+    }
+
+    #[test]
+    fn toggling_leaves_other_emphasis_alone() {
+        let italic = run(1..5, Emphasis::Italic);
+        let runs = toggled(&[italic.clone()], 1..5, Emphasis::Bold);
+        assert_eq!(runs, [italic.clone(), bold(1..5)]);
+        assert_eq!(toggled(&runs, 1..5, Emphasis::Bold), [italic]);
+    }
+
+    #[test]
+    fn empty_ranges_and_runs_change_nothing() {
+        assert_eq!(toggled(&[bold(2..6)], 3..3, Emphasis::Bold), [bold(2..6)]);
+        // An empty run does not make the range emphasized already.
+        let runs = toggled(&[bold(2..2)], 2..4, Emphasis::Bold);
+        assert_eq!(mask(&runs, Emphasis::Bold, 4), "..xx");
+    }
+
+    #[test]
+    fn markup_nests_emphasis_and_escapes_text() {
+        let runs = [bold(1..3), run(1..5, Emphasis::Italic)];
+        assert_eq!(markup("héllo", &runs), "h<b><i>é</i></b><i>ll</i>o");
+        assert_eq!(markup("a<b & c", &[]), "a&lt;b &amp; c");
+    }
+}
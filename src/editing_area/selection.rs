@@ -1,14 +1,162 @@
+use std::fmt;
 use std::ops::{Deref, DerefMut};
+use std::str::FromStr;
 
 use glib::object::IsA;
-use gtk4::prelude::{SnapshotExt, WidgetExt};
+use gdk4::prelude::SurfaceExt;
+use gtk4::prelude::{NativeExt, SnapshotExt, WidgetExt};
 
 #[derive(Debug, Clone, Copy)]
 pub struct Selection {
     start: (f32, f32),
     end: (f32, f32),
+    /// Limits on the shape, kept by [`Self::reshape`] and friends.
+    constraint: SelectionConstraint,
 }
 
+/// How a [`Selection`] is allowed to be reshaped.
+#[derive(Debug, Clone, Copy, PartialEq, Default, glib::Boxed)]
+#[boxed_type(name = "SelectionConstraint")]
+pub enum SelectionConstraint {
+    /// Any shape.
+    #[default]
+    Free,
+    /// Width divided by height stays the same.
+    Ratio(f32),
+    /// Always exactly this size.
+    Fixed { width: f32, height: f32 },
+}
+
+impl SelectionConstraint {
+    /// The presets cycled through in the editor.
+    pub const PRESETS: [SelectionConstraint; 7] = [
+        SelectionConstraint::Free,
+        SelectionConstraint::Ratio(1.0),
+        SelectionConstraint::Ratio(4.0 / 3.0),
+        SelectionConstraint::Ratio(16.0 / 9.0),
+        SelectionConstraint::Fixed { width: 1280.0, height: 720.0 },
+        SelectionConstraint::Fixed { width: 1920.0, height: 1080.0 },
+        SelectionConstraint::Fixed { width: 1200.0, height: 630.0 },
+    ];
+
+    /// The preset after this one, back to [`Self::Free`] after the last one
+    /// or when this is not a preset.
+    pub fn next_preset(self) -> SelectionConstraint {
+        let index = Self::PRESETS.iter().position(|preset| *preset == self);
+        index
+            .and_then(|index| Self::PRESETS.get(index + 1))
+            .copied()
+            .unwrap_or(SelectionConstraint::Free)
+    }
+}
+
+/// Formats as accepted by [`FromStr`]: `free`, a ratio like `16:9` or a
+/// size like `1280x720`.
+impl fmt::Display for SelectionConstraint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SelectionConstraint::Free => write!(f, "free"),
+            SelectionConstraint::Ratio(ratio) => {
+                // Show the usual ratios the way people write them.
+                for height in 1..=20 {
+                    let width = ratio * height as f32;
+                    if (width - width.round()).abs() < 1e-3 {
+                        return write!(f, "{}:{}", width.round(), height);
+                    }
+                }
+                write!(f, "{}:1", ratio)
+            }
+            SelectionConstraint::Fixed { width, height } => write!(f, "{}x{}", width, height),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseConstraintError(String);
+
+impl fmt::Display for ParseConstraintError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "invalid constraint {:?}, expected \"free\", a ratio like \"16:9\" or a size like \"1280x720\"",
+            self.0
+        )
+    }
+}
+
+impl std::error::Error for ParseConstraintError {}
+
+impl FromStr for SelectionConstraint {
+    type Err = ParseConstraintError;
+
+    fn from_str(constraint: &str) -> Result<Self, Self::Err> {
+        let error = || ParseConstraintError(constraint.to_string());
+        let number = |text: &str| {
+            text.trim()
+                .parse::<f32>()
+                .ok()
+                .filter(|number| number.is_finite() && *number > 0.0)
+                .ok_or_else(error)
+        };
+
+        let constraint = constraint.trim();
+        if constraint.eq_ignore_ascii_case("free") {
+            Ok(SelectionConstraint::Free)
+        } else if let Some((width, height)) = constraint.split_once(':') {
+            Ok(SelectionConstraint::Ratio(number(width)? / number(height)?))
+        } else if let Some((width, height)) = constraint.split_once('x') {
+            Ok(SelectionConstraint::Fixed {
+                width: number(width)?,
+                height: number(height)?,
+            })
+        } else {
+            Err(error())
+        }
+    }
+}
+
+/// -1 for negative values, 1 otherwise, so a collapsed selection grows
+/// towards the bottom right.
+const fn direction(value: f32) -> f32 {
+    if value < 0.0 { -1.0 } else { 1.0 }
+}
+
+/// Moves `moving` so the rectangle spanned with `anchor` satisfies the
+/// constraint, on the same side of `anchor` it already was.
+const fn constrain_corner(
+    moving: (f32, f32),
+    anchor: (f32, f32),
+    constraint: SelectionConstraint,
+) -> (f32, f32) {
+    let dx = moving.0 - anchor.0;
+    let dy = moving.1 - anchor.1;
+    let (width, height) = match constraint {
+        SelectionConstraint::Free => return moving,
+        SelectionConstraint::Ratio(ratio) => {
+            // Grow the short side, so the selection still reaches the pointer.
+            let width = dx.abs();
+            let height = dy.abs();
+            if width > height * ratio {
+                (width, width / ratio)
+            } else {
+                (height * ratio, height)
+            }
+        }
+        SelectionConstraint::Fixed { width, height } => (width, height),
+    };
+    (anchor.0 + direction(dx) * width, anchor.1 + direction(dy) * height)
+}
+
+/// Side length of the knobs drawn on the handles.
+const KNOB_SIZE: f32 = 8.0;
+/// How far from a handle the pointer can be and still grab it.
+const HANDLE_TOLERANCE: f32 = 6.0;
+/// How close an edge has to get to a window or output edge to snap to it.
+const SNAP_THRESHOLD: f32 = 8.0;
+/// Room around the text of the constraint label.
+const LABEL_PADDING: f32 = 4.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum DragHandle {
     TopLeft,
     TopRight,
@@ -20,9 +168,185 @@ pub enum DragHandle {
     Right,
 }
 
+impl DragHandle {
+    /// Corners first, so they win over edges where they overlap.
+    pub const ALL: [DragHandle; 8] = [
+        DragHandle::TopLeft,
+        DragHandle::TopRight,
+        DragHandle::BottomLeft,
+        DragHandle::BottomRight,
+        DragHandle::Top,
+        DragHandle::Bottom,
+        DragHandle::Left,
+        DragHandle::Right,
+    ];
+
+    /// The next handle clockwise.
+    pub const fn next(self) -> DragHandle {
+        match self {
+            DragHandle::TopLeft => DragHandle::Top,
+            DragHandle::Top => DragHandle::TopRight,
+            DragHandle::TopRight => DragHandle::Right,
+            DragHandle::Right => DragHandle::BottomRight,
+            DragHandle::BottomRight => DragHandle::Bottom,
+            DragHandle::Bottom => DragHandle::BottomLeft,
+            DragHandle::BottomLeft => DragHandle::Left,
+            DragHandle::Left => DragHandle::TopLeft,
+        }
+    }
+
+    /// The next handle counterclockwise.
+    pub const fn previous(self) -> DragHandle {
+        match self {
+            DragHandle::TopLeft => DragHandle::Left,
+            DragHandle::Top => DragHandle::TopLeft,
+            DragHandle::TopRight => DragHandle::Top,
+            DragHandle::Right => DragHandle::TopRight,
+            DragHandle::BottomRight => DragHandle::Right,
+            DragHandle::Bottom => DragHandle::BottomRight,
+            DragHandle::BottomLeft => DragHandle::Bottom,
+            DragHandle::Left => DragHandle::BottomLeft,
+        }
+    }
+
+    /// Where the handle's knob sits on the selection.
+    pub const fn position(self, selection: &Selection) -> (f32, f32) {
+        let (center_x, center_y) = selection.center();
+        match self {
+            DragHandle::TopLeft => selection.top_left(),
+            DragHandle::TopRight => selection.top_right(),
+            DragHandle::BottomLeft => selection.bottom_left(),
+            DragHandle::BottomRight => selection.bottom_right(),
+            DragHandle::Top => (center_x, selection.top()),
+            DragHandle::Bottom => (center_x, selection.bottom()),
+            DragHandle::Left => (selection.left(), center_y),
+            DragHandle::Right => (selection.right(), center_y),
+        }
+    }
+
+    /// Name of the CSS cursor shown while hovering the handle.
+    pub const fn cursor_name(self) -> &'static str {
+        match self {
+            DragHandle::TopLeft => "nw-resize",
+            DragHandle::TopRight => "ne-resize",
+            DragHandle::BottomLeft => "sw-resize",
+            DragHandle::BottomRight => "se-resize",
+            DragHandle::Top => "n-resize",
+            DragHandle::Bottom => "s-resize",
+            DragHandle::Left => "w-resize",
+            DragHandle::Right => "e-resize",
+        }
+    }
+
+    /// The handle held after [`Selection::reshape`] dragged this one to
+    /// `point`. Dragging an edge past the opposite one turns the selection
+    /// inside out, and from then on the pointer holds the other side.
+    pub const fn after_reshape(self, selection: &Selection, point: (f32, f32)) -> DragHandle {
+        let (center_x, center_y) = selection.center();
+        let left = point.0 < center_x;
+        let top = point.1 < center_y;
+        match self {
+            DragHandle::TopLeft
+            | DragHandle::TopRight
+            | DragHandle::BottomLeft
+            | DragHandle::BottomRight => match (left, top) {
+                (true, true) => DragHandle::TopLeft,
+                (false, true) => DragHandle::TopRight,
+                (true, false) => DragHandle::BottomLeft,
+                (false, false) => DragHandle::BottomRight,
+            },
+            DragHandle::Top | DragHandle::Bottom => {
+                if top { DragHandle::Top } else { DragHandle::Bottom }
+            }
+            DragHandle::Left | DragHandle::Right => {
+                if left { DragHandle::Left } else { DragHandle::Right }
+            }
+        }
+    }
+}
+
+/// The corner a fresh selection is dragged out by.
+impl Default for DragHandle {
+    fn default() -> Self {
+        DragHandle::BottomRight
+    }
+}
+
+/// Edges a selection snaps to, such as the ones of windows and outputs, in
+/// widget coordinates.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SnapTargets {
+    /// x coordinates of vertical edges.
+    xs: Vec<f32>,
+    /// y coordinates of horizontal edges.
+    ys: Vec<f32>,
+}
+
+impl SnapTargets {
+    pub fn from_rects(rects: &[graphene::Rect]) -> Self {
+        let mut targets = SnapTargets::default();
+        for rect in rects {
+            targets.xs.extend([rect.x(), rect.x() + rect.width()]);
+            targets.ys.extend([rect.y(), rect.y() + rect.height()]);
+        }
+        targets
+    }
+
+    /// The closest of `edges` within the threshold of `value`, or `value`.
+    fn snap(edges: &[f32], value: f32) -> f32 {
+        edges
+            .iter()
+            .copied()
+            .filter(|edge| (edge - value).abs() <= SNAP_THRESHOLD)
+            .min_by(|a, b| (a - value).abs().total_cmp(&(b - value).abs()))
+            .unwrap_or(value)
+    }
+
+    pub fn snap_x(&self, x: f32) -> f32 {
+        Self::snap(&self.xs, x)
+    }
+
+    pub fn snap_y(&self, y: f32) -> f32 {
+        Self::snap(&self.ys, y)
+    }
+
+    /// The smallest shift that snaps one of `values` to an edge, if any.
+    fn shift(edges: &[f32], values: [f32; 2]) -> f32 {
+        values
+            .into_iter()
+            .map(|value| Self::snap(edges, value) - value)
+            .filter(|shift| *shift != 0.0)
+            .min_by(|a, b| a.abs().total_cmp(&b.abs()))
+            .unwrap_or(0.0)
+    }
+}
+
+/// The part of a selection under the pointer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SelectionHit {
+    /// Dragging it reshapes the selection.
+    Handle(DragHandle),
+    /// Dragging it moves the selection.
+    Interior,
+}
+
+impl SelectionHit {
+    /// Name of the CSS cursor shown while hovering this part.
+    pub const fn cursor_name(self) -> &'static str {
+        match self {
+            SelectionHit::Handle(handle) => handle.cursor_name(),
+            SelectionHit::Interior => "move",
+        }
+    }
+}
+
 impl Selection {
     pub const fn new(start: (f32, f32), end: (f32, f32)) -> Self {
-        Selection { start, end }
+        Selection {
+            start,
+            end,
+            constraint: SelectionConstraint::Free,
+        }
     }
 
     pub const fn new_from_size(
@@ -31,7 +355,7 @@ impl Selection {
         height: f32,
     ) -> Self {
         let end = (start.0 + width, start.1 + height);
-        Selection { start, end }
+        Selection::new(start, end)
     }
 
     pub const fn start(&self) -> (f32, f32) {
@@ -47,6 +371,101 @@ impl Selection {
         self.end = end;
     }
 
+    pub const fn constraint(&self) -> SelectionConstraint {
+        self.constraint
+    }
+    /// Sets the constraint the next reshapes keep, without reshaping now.
+    pub const fn set_constraint(&mut self, constraint: SelectionConstraint) {
+        self.constraint = constraint;
+    }
+    /// Reshapes the selection to satisfy its constraint, keeping the
+    /// top-left corner in place.
+    pub const fn enforce_constraint(&mut self) {
+        self.normalize();
+        self.constrain(DragHandle::BottomRight);
+    }
+    /// Width divided by height, 1 for an empty selection.
+    pub const fn ratio(&self) -> f32 {
+        if self.width() > 0.0 && self.height() > 0.0 {
+            self.width() / self.height()
+        } else {
+            1.0
+        }
+    }
+
+    /// Drags the end corner to `end` while a new selection is dragged out,
+    /// the start corner staying put.
+    pub const fn drag_end_to(&mut self, end: (f32, f32)) {
+        self.end = constrain_corner(end, self.start, self.constraint);
+    }
+
+    /// Makes the selection satisfy its constraint after `handle` moved,
+    /// keeping the opposite corner or edge in place.
+    ///
+    /// Relies on the way the `move_*` functions below leave the corners:
+    /// the handles on top and left of the selection move `start`, the
+    /// others move `end`, except for top-right and bottom-left which move
+    /// `start` and `end` respectively.
+    const fn constrain(&mut self, handle: DragHandle) {
+        let constraint = self.constraint;
+        if let SelectionConstraint::Free = constraint {
+            return;
+        }
+        match handle {
+            DragHandle::TopLeft | DragHandle::TopRight => {
+                self.start = constrain_corner(self.start, self.end, constraint);
+            }
+            DragHandle::BottomLeft | DragHandle::BottomRight => {
+                self.end = constrain_corner(self.end, self.start, constraint);
+            }
+            DragHandle::Top | DragHandle::Bottom => {
+                let (moving, anchor) = match handle {
+                    DragHandle::Top => (self.start.1, self.end.1),
+                    _ => (self.end.1, self.start.1),
+                };
+                let (width, height) = match constraint {
+                    SelectionConstraint::Fixed { width, height } => (width, height),
+                    SelectionConstraint::Ratio(ratio) => {
+                        let height = (moving - anchor).abs();
+                        (height * ratio, height)
+                    }
+                    SelectionConstraint::Free => return,
+                };
+                let moving = anchor + direction(moving - anchor) * height;
+                match handle {
+                    DragHandle::Top => self.start.1 = moving,
+                    _ => self.end.1 = moving,
+                }
+                // The width follows, centered where it was.
+                let center_x = (self.start.0 + self.end.0) / 2.0;
+                self.start.0 = center_x - width / 2.0;
+                self.end.0 = center_x + width / 2.0;
+            }
+            DragHandle::Left | DragHandle::Right => {
+                let (moving, anchor) = match handle {
+                    DragHandle::Left => (self.start.0, self.end.0),
+                    _ => (self.end.0, self.start.0),
+                };
+                let (width, height) = match constraint {
+                    SelectionConstraint::Fixed { width, height } => (width, height),
+                    SelectionConstraint::Ratio(ratio) => {
+                        let width = (moving - anchor).abs();
+                        (width, width / ratio)
+                    }
+                    SelectionConstraint::Free => return,
+                };
+                let moving = anchor + direction(moving - anchor) * width;
+                match handle {
+                    DragHandle::Left => self.start.0 = moving,
+                    _ => self.end.0 = moving,
+                }
+                let center_y = (self.start.1 + self.end.1) / 2.0;
+                self.start.1 = center_y - height / 2.0;
+                self.end.1 = center_y + height / 2.0;
+            }
+        }
+    }
+
     /// Returns the top-left corner of the selection.
     pub const fn top_left(&self) -> (f32, f32) {
         let top = self.start.1.min(self.end.1);
@@ -207,6 +626,56 @@ impl Selection {
         y >= start_y && y <= end_y
     }
 
+    /// Snaps the corner or edge held by `handle` to the nearest targets,
+    /// the snapping pass after [`Self::reshape`].
+    pub fn snap_handle(&mut self, handle: DragHandle, targets: &SnapTargets) {
+        let (x, y) = handle.position(self);
+        self.reshape(handle, targets.snap_x(x), targets.snap_y(y));
+    }
+
+    /// Snaps the end corner, the one following the pointer while a new
+    /// selection is dragged out.
+    pub fn snap_end(&mut self, targets: &SnapTargets) {
+        self.drag_end_to((targets.snap_x(self.end.0), targets.snap_y(self.end.1)));
+    }
+
+    /// Shifts the whole selection, keeping its size, so its closest edges
+    /// line up with the targets.
+    pub fn snap_move(&mut self, targets: &SnapTargets) {
+        let dx = SnapTargets::shift(&targets.xs, [self.left(), self.right()]);
+        let dy = SnapTargets::shift(&targets.ys, [self.top(), self.bottom()]);
+        self.move_relative(dx, dy);
+    }
+
+    /// Finds the handle or interior under `point`, if any.
+    pub fn hit_test(&self, point: (f32, f32)) -> Option<SelectionHit> {
+        let (x, y) = point;
+        let near = |a: f32, b: f32| (a - b).abs() <= HANDLE_TOLERANCE;
+        let within = |value: f32, low: f32, high: f32| {
+            value >= low - HANDLE_TOLERANCE && value <= high + HANDLE_TOLERANCE
+        };
+
+        for handle in DragHandle::ALL {
+            let (handle_x, handle_y) = handle.position(self);
+            // Edges can be grabbed anywhere along them, not just at the knob.
+            let hit = match handle {
+                DragHandle::Top | DragHandle::Bottom => {
+                    near(y, handle_y) && within(x, self.left(), self.right())
+                }
+                DragHandle::Left | DragHandle::Right => {
+                    near(x, handle_x) && within(y, self.top(), self.bottom())
+                }
+                _ => near(x, handle_x) && near(y, handle_y),
+            };
+            if hit {
+                return Some(SelectionHit::Handle(handle));
+            }
+        }
+
+        let inside = x >= self.left() && x <= self.right() && y >= self.top() && y <= self.bottom();
+        inside.then_some(SelectionHit::Interior)
+    }
+
     /// Moves the selection's top-left corner to the given coordinates.
     const fn move_top_left(&mut self, x: f32, y: f32) {
         self.normalize();
@@ -313,6 +782,7 @@ impl Selection {
             DragHandle::Left => self.move_left(x),
             DragHandle::Right => self.move_right(x),
         }
+        self.constrain(handle);
     }
 
     /// Reshapes the selection based on the given drag handle and relative coordinates.
@@ -332,9 +802,33 @@ impl Selection {
             DragHandle::Left => self.move_left_relative(dx),
             DragHandle::Right => self.move_right_relative(dx),
         }
+        self.constrain(handle);
+    }
+
+    /// The selection in another coordinate space, moved by (`dx`, `dy`).
+    /// Widget coordinates plus the widget's origin are global logical
+    /// coordinates, and the other way around.
+    pub const fn translated(mut self, dx: f32, dy: f32) -> Self {
+        self.move_relative(dx, dy);
+        self
     }
 
-    /// Converts the selection to a `graphene::Rect`.
+    /// The selection in buffer pixels as (x, y, width, height), with `scale`
+    /// buffer pixels per logical pixel, relative to the logical `origin`.
+    ///
+    /// Each edge is rounded to the nearest buffer pixel, the same way the
+    /// editing area draws it at that scale, so what is saved is exactly what
+    /// was shown as selected.
+    pub fn to_buffer_rect(&self, origin: (f32, f32), scale: f64) -> (i32, i32, i32, i32) {
+        let (left, top) = self.top_left();
+        let (right, bottom) = self.bottom_right();
+        let to_buffer = |logical: f32, origin: f32| ((logical - origin) as f64 * scale).round() as i32;
+        let (x, y) = (to_buffer(left, origin.0), to_buffer(top, origin.1));
+        (x, y, to_buffer(right, origin.0) - x, to_buffer(bottom, origin.1) - y)
+    }
+
+    /// Converts the selection to a `graphene::Rect`, rounded to whole
+    /// logical pixels.
     pub fn to_graphene_rect(&self) -> graphene::Rect {
         let (start_x, start_y) = self.top_left();
         graphene::Rect::new(
@@ -345,16 +839,20 @@ impl Selection {
         )
     }
 
+    /// Converts the selection to a `graphene::Rect` whose edges fall on
+    /// device pixels, with `scale` device pixels per logical pixel.
+    pub fn to_device_rect(&self, scale: f64) -> graphene::Rect {
+        let (x, y, width, height) = self.to_buffer_rect((0.0, 0.0), scale);
+        let to_logical = |device: i32| (device as f64 / scale) as f32;
+        graphene::Rect::new(to_logical(x), to_logical(y), to_logical(width), to_logical(height))
+    }
+
     /// Converts the selection to a `graphene::Rect`, positioned to be
-    /// drawn as a 1px border around the selection.
-    pub fn to_graphene_border_rect(&self) -> graphene::Rect {
-        let (start_x, start_y) = self.top_left();
-        graphene::Rect::new(
-            start_x.round() - 0.5,
-            start_y.round() - 0.5,
-            self.width().round() + 1.0,
-            self.height().round() + 1.0,
-        )
+    /// drawn as a border `line_width` wide around the selection, with
+    /// `scale` device pixels per logical pixel.
+    pub fn to_graphene_border_rect(&self, scale: f64, line_width: f32) -> graphene::Rect {
+        let rect = self.to_device_rect(scale);
+        rect.inset_r(-line_width / 2.0, -line_width / 2.0)
     }
 
     /// Draws the selection to a `gtk4::Snapshot`.
@@ -371,8 +869,10 @@ impl Selection {
         let height = widget.height() as f32;
         let bounds = graphene::Rect::new(0.0, 0.0, width, height);
 
+        let scale = device_scale(&widget);
+
         snapshot.push_mask(gsk4::MaskMode::InvertedAlpha); // every mask needs to be paired with two pops
-        let selection_rect = self.to_graphene_rect();
+        let selection_rect = self.to_device_rect(scale);
         snapshot.append_color(&gdk4::RGBA::WHITE, &selection_rect);
 
         // apply the mask and make it active
@@ -384,17 +884,119 @@ impl Selection {
 
         // stop using the mask
         snapshot.pop();
-        
-        let border_selection_rect = self.to_graphene_border_rect();
+
+        self.draw_frame_to_snapshot(snapshot, widget);
+    }
+
+    /// Draws the border of the selection and a knob on every handle.
+    pub fn draw_frame_to_snapshot(&self, snapshot: &gtk4::Snapshot, widget: impl IsA<gtk4::Widget>) {
+        let scale = device_scale(&widget);
+        // About one logical pixel, but a whole number of device pixels so
+        // it is not smeared over two of them at fractional scales.
+        let line_width = (scale.round().max(1.0) / scale) as f32;
+        let border_selection_rect = self.to_graphene_border_rect(scale, line_width);
         let path_builder = gsk4::PathBuilder::new();
         path_builder.add_rect(&border_selection_rect);
         let path = path_builder.to_path();
-        let stroke = gsk4::Stroke::new(1.0);
+        let stroke = gsk4::Stroke::new(line_width);
 
         snapshot.append_stroke(&path, &stroke, &gdk4::RGBA::WHITE);
+
+        self.draw_knobs_to_snapshot(snapshot, scale);
+    }
+
+    /// Labels the selection with its constraint, unless it is free, just
+    /// above its top-left corner or inside it when that is off the widget.
+    pub fn draw_constraint_to_snapshot(&self, snapshot: &gtk4::Snapshot, widget: impl IsA<gtk4::Widget>) {
+        if self.constraint == SelectionConstraint::Free || self.width() <= 0.0 || self.height() <= 0.0 {
+            return;
+        }
+        let layout = widget.create_pango_layout(Some(&self.constraint.to_string()));
+        let (text_width, text_height) = layout.pixel_size();
+        let (width, height) = (
+            text_width as f32 + 2.0 * LABEL_PADDING,
+            text_height as f32 + 2.0 * LABEL_PADDING,
+        );
+        let (left, top) = self.top_left();
+        let y = if top - height >= 0.0 { top - height } else { top };
+        let label = graphene::Rect::new(left.round(), y.round(), width, height);
+
+        snapshot.append_color(&gdk4::RGBA::new(0.0, 0.0, 0.0, 0.7), &label);
+        snapshot.save();
+        snapshot.translate(&graphene::Point::new(label.x() + LABEL_PADDING, label.y() + LABEL_PADDING));
+        snapshot.append_layout(&layout, &gdk4::RGBA::WHITE);
+        snapshot.restore();
+    }
+
+    /// Marks `handle` as the one keyboard reshaping acts on, drawn over its
+    /// knob in the accent blue.
+    pub fn draw_active_handle_to_snapshot(
+        &self,
+        snapshot: &gtk4::Snapshot,
+        widget: impl IsA<gtk4::Widget>,
+        handle: DragHandle,
+    ) {
+        if self.width() <= 0.0 || self.height() <= 0.0 {
+            return;
+        }
+        let knob = knob_rect(handle.position(self), device_scale(&widget));
+        let accent = gdk4::RGBA::new(0.21, 0.52, 0.89, 1.0);
+        snapshot.append_color(&accent, &knob);
+    }
+
+    /// Draws a knob on every handle, white with a dark outline so it stands
+    /// out on any background.
+    fn draw_knobs_to_snapshot(&self, snapshot: &gtk4::Snapshot, scale: f64) {
+        let path_builder = gsk4::PathBuilder::new();
+        for handle in DragHandle::ALL {
+            path_builder.add_rect(&knob_rect(handle.position(self), scale));
+        }
+        fill_knobs(snapshot, &path_builder.to_path());
     }
 }
 
+/// Draws a knob like the selection's on `position`, for a handle that is
+/// not on the edge of a selection.
+pub fn draw_knob_to_snapshot(snapshot: &gtk4::Snapshot, widget: impl IsA<gtk4::Widget>, position: (f32, f32)) {
+    let path_builder = gsk4::PathBuilder::new();
+    path_builder.add_rect(&knob_rect(position, device_scale(&widget)));
+    fill_knobs(snapshot, &path_builder.to_path());
+}
+
+/// Whether `point` is close enough to a knob on `position` to grab it.
+pub fn near_knob(position: (f32, f32), point: (f32, f32)) -> bool {
+    (point.0 - position.0).abs() <= HANDLE_TOLERANCE && (point.1 - position.1).abs() <= HANDLE_TOLERANCE
+}
+
+fn fill_knobs(snapshot: &gtk4::Snapshot, path: &gsk4::Path) {
+    snapshot.append_fill(path, gsk4::FillRule::Winding, &gdk4::RGBA::WHITE);
+    let outline = gdk4::RGBA::new(0.0, 0.0, 0.0, 0.7);
+    snapshot.append_stroke(path, &gsk4::Stroke::new(1.0), &outline);
+}
+
+/// Device pixels per logical pixel of the surface `widget` is shown on,
+/// fractional with `wp-fractional-scale-v1`.
+fn device_scale(widget: &impl IsA<gtk4::Widget>) -> f64 {
+    widget
+        .native()
+        .and_then(|native| native.surface())
+        .map(|surface| surface.scale())
+        .filter(|scale| *scale > 0.0)
+        .unwrap_or_else(|| widget.scale_factor() as f64)
+}
+
+/// The knob centered on `position`, its edges on device pixels.
+fn knob_rect(position: (f32, f32), scale: f64) -> graphene::Rect {
+    let snap = |logical: f32| ((logical as f64 * scale).round() / scale) as f32;
+    let size = snap(KNOB_SIZE);
+    graphene::Rect::new(
+        snap(position.0 - KNOB_SIZE / 2.0),
+        snap(position.1 - KNOB_SIZE / 2.0),
+        size,
+        size,
+    )
+}
+
 impl PartialEq for Selection {
     /// Checks if the given 2 selections are equal in their positioning and size.
     /// (with a tolerance of `f32::EPSILON` for floating point comparisons)
@@ -410,12 +1012,57 @@ impl PartialEq for Selection {
     }
 }
 
+/// Formats the selection the way slurp does, `"x,y wxh"`, rounded to
+/// whole pixels.
+impl fmt::Display for Selection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let rect = self.to_graphene_rect();
+        write!(
+            f,
+            "{},{} {}x{}",
+            rect.x(),
+            rect.y(),
+            rect.width(),
+            rect.height()
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseGeometryError(String);
+
+impl fmt::Display for ParseGeometryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid geometry {:?}, expected \"x,y wxh\"", self.0)
+    }
+}
+
+impl std::error::Error for ParseGeometryError {}
+
+/// Parses a slurp style `"x,y wxh"` geometry.
+impl FromStr for Selection {
+    type Err = ParseGeometryError;
+
+    fn from_str(geometry: &str) -> Result<Self, Self::Err> {
+        let error = || ParseGeometryError(geometry.to_string());
+        let number = |text: &str| text.trim().parse::<f32>().ok().filter(|n| n.is_finite());
+
+        let (position, size) = geometry.trim().split_once(' ').ok_or_else(error)?;
+        let (x, y) = position.split_once(',').ok_or_else(error)?;
+        let (width, height) = size.trim().split_once('x').ok_or_else(error)?;
+        let (x, y) = (number(x).ok_or_else(error)?, number(y).ok_or_else(error)?);
+        let (width, height) = (number(width).ok_or_else(error)?, number(height).ok_or_else(error)?);
+        // Nothing to capture in an empty rectangle.
+        if width <= 0.0 || height <= 0.0 {
+            return Err(error());
+        }
+        Ok(Selection::new_from_size((x, y), width, height))
+    }
+}
+
 impl Default for Selection {
     fn default() -> Self {
-        Selection {
-            start: (0.0, 0.0),
-            end: (1.0, 1.0),
-        }
+        Selection::new((0.0, 0.0), (1.0, 1.0))
     }
 }
 
@@ -469,6 +1116,18 @@ impl MaybeSelection {
     }
 }
 
+impl MaybeSelection {
+    /// See [`Selection::translated`].
+    pub const fn translated(self, dx: f32, dy: f32) -> Self {
+        match self {
+            MaybeSelection::Selection(selection) => {
+                MaybeSelection::Selection(selection.translated(dx, dy))
+            }
+            no_selection => no_selection,
+        }
+    }
+}
+
 impl Default for MaybeSelection {
     fn default() -> Self {
         MaybeSelection::NoSelection(NoSelection::new())
@@ -511,4 +1170,142 @@ impl AsMut<MaybeSelection> for MaybeSelectionBoxed {
     fn as_mut(&mut self) -> &mut MaybeSelection {
         &mut self.0
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(geometry: &str) -> Result<Selection, ParseGeometryError> {
+        geometry.parse::<Selection>()
+    }
+
+    #[test]
+    fn geometry_round_trips() {
+        for geometry in ["0,0 1x1", "10,20 300x200", "1920,0 2560x1440"] {
+            assert_eq!(parse(geometry).unwrap().to_string(), geometry);
+        }
+    }
+
+    #[test]
+    fn geometry_allows_negative_origins() {
+        // Outputs left of or above the primary one.
+        let selection = parse("-1920,-120 1920x1080").unwrap();
+        assert_eq!(selection.top_left(), (-1920.0, -120.0));
+        assert_eq!((selection.width(), selection.height()), (1920.0, 1080.0));
+        assert_eq!(selection.to_string(), "-1920,-120 1920x1080");
+    }
+
+    #[test]
+    fn geometry_is_printed_in_whole_pixels() {
+        let selection = Selection::new_from_size((10.4, 19.6), 99.7, 50.2);
+        assert_eq!(selection.to_string(), "10,20 100x50");
+    }
+
+    #[test]
+    fn geometry_accepts_surrounding_whitespace() {
+        assert_eq!(parse(" 1,2 3x4\n").unwrap().to_string(), "1,2 3x4");
+    }
+
+    #[test]
+    fn geometry_rejects_malformed_input() {
+        for geometry in ["", "1,2", "1,2 3x", "1,2 x4", "a,b cxd", "1 2x3", "1,2 3x4x5", "1,2 3*4", "1,2 NaNx4"] {
+            assert!(parse(geometry).is_err(), "{:?} should not parse", geometry);
+        }
+    }
+
+    #[test]
+    fn geometry_rejects_empty_and_negative_sizes() {
+        for geometry in ["0,0 0x10", "0,0 10x0", "0,0 -5x10", "0,0 10x-5"] {
+            assert!(parse(geometry).is_err(), "{:?} should not parse", geometry);
+        }
+    }
+
+    /// Asserts the selection spans (x, y, width, height), give or take
+    /// float rounding.
+    fn assert_rect(selection: &Selection, expected: (f32, f32, f32, f32)) {
+        let (x, y) = selection.top_left();
+        let actual = (x, y, selection.width(), selection.height());
+        let close = |a: f32, b: f32| (a - b).abs() < 1e-3;
+        assert!(
+            close(actual.0, expected.0)
+                && close(actual.1, expected.1)
+                && close(actual.2, expected.2)
+                && close(actual.3, expected.3),
+            "{:?} != {:?}",
+            actual,
+            expected
+        );
+    }
+
+    /// A 320x180 selection at the origin, kept to `constraint`.
+    fn constrained(constraint: SelectionConstraint) -> Selection {
+        let mut selection = Selection::new_from_size((0.0, 0.0), 320.0, 180.0);
+        selection.set_constraint(constraint);
+        selection
+    }
+
+    const WIDESCREEN: SelectionConstraint = SelectionConstraint::Ratio(16.0 / 9.0);
+    const FIXED: SelectionConstraint = SelectionConstraint::Fixed { width: 800.0, height: 600.0 };
+
+    #[test]
+    fn ratio_corner_grows_the_short_side() {
+        let mut selection = constrained(WIDESCREEN);
+        selection.reshape(DragHandle::BottomRight, 400.0, 100.0);
+        assert_rect(&selection, (0.0, 0.0, 400.0, 225.0));
+
+        // The opposite corner stays put.
+        let mut selection = constrained(WIDESCREEN);
+        selection.reshape(DragHandle::TopLeft, -80.0, 90.0);
+        assert_rect(&selection, (-80.0, -45.0, 400.0, 225.0));
+    }
+
+    #[test]
+    fn ratio_edge_keeps_the_other_side_centered() {
+        let mut selection = constrained(WIDESCREEN);
+        selection.reshape(DragHandle::Right, 640.0, 0.0);
+        assert_rect(&selection, (0.0, -90.0, 640.0, 360.0));
+
+        let mut selection = constrained(WIDESCREEN);
+        selection.reshape(DragHandle::Top, 0.0, -180.0);
+        assert_rect(&selection, (-160.0, -180.0, 640.0, 360.0));
+    }
+
+    #[test]
+    fn fixed_corner_keeps_the_size() {
+        let mut selection = constrained(FIXED);
+        selection.reshape(DragHandle::BottomRight, 10.0, 10.0);
+        assert_rect(&selection, (0.0, 0.0, 800.0, 600.0));
+
+        // Dragged past the opposite corner, it flips to that side.
+        let mut selection = constrained(FIXED);
+        selection.reshape(DragHandle::TopLeft, 500.0, 500.0);
+        assert_rect(&selection, (320.0, 180.0, 800.0, 600.0));
+    }
+
+    #[test]
+    fn fixed_edge_keeps_the_size() {
+        let mut selection = constrained(FIXED);
+        selection.reshape(DragHandle::Left, 100.0, 0.0);
+        assert_rect(&selection, (-480.0, -210.0, 800.0, 600.0));
+    }
+
+    #[test]
+    fn constraint_parses() {
+        let parse = |constraint: &str| constraint.parse::<SelectionConstraint>();
+        assert_eq!(parse("free"), Ok(SelectionConstraint::Free));
+        assert_eq!(parse("16:9"), Ok(WIDESCREEN));
+        assert_eq!(parse(" 4:3 "), Ok(SelectionConstraint::Ratio(4.0 / 3.0)));
+        assert_eq!(parse("800x600"), Ok(FIXED));
+        for constraint in ["", "16/9", "16:0", "-4:3", "0x600", "800x", "axb"] {
+            assert!(parse(constraint).is_err(), "{:?} should not parse", constraint);
+        }
+    }
+
+    #[test]
+    fn constraint_round_trips() {
+        for constraint in [SelectionConstraint::Free, WIDESCREEN, FIXED] {
+            assert_eq!(constraint.to_string().parse::<SelectionConstraint>(), Ok(constraint));
+        }
+    }
+}
@@ -0,0 +1,310 @@
+//! Typing the text of a text command or callout in place.
+
+use glib::object::IsA;
+use gtk4::prelude::{SnapshotExt, WidgetExt};
+
+use super::command::{Command, CommandType};
+use super::rich_text::{self, Emphasis, TextRun, TextStyle};
+
+/// Color of the caret and of the selected text's background.
+const ACCENT: gdk4::RGBA = gdk4::RGBA::new(0.21, 0.52, 0.89, 1.0);
+
+/// A text command being typed in. It is drawn from here until editing
+/// ends, and only then goes on the undo stack, as a single change.
+#[derive(Debug, Clone)]
+pub struct TextEdit {
+    /// Index of the change that added the command when an existing one was
+    /// reopened, `None` for new text.
+    pub index: Option<usize>,
+    /// The command as it was before editing, if it already existed.
+    pub original: Option<Command>,
+    /// The command with the text typed so far, always one with text.
+    pub command: Command,
+    /// Byte offset of the caret in the text.
+    pub cursor: usize,
+    /// The other end of the selected text, the caret itself when nothing
+    /// is selected.
+    pub anchor: usize,
+    /// What the input method is composing, shown at the caret but not part
+    /// of the text until committed.
+    pub preedit: String,
+    /// Byte offset of the input method's own caret in `preedit`.
+    pub preedit_cursor: usize,
+}
+
+impl TextEdit {
+    /// Starts editing `command` with the caret at the end of its text.
+    pub fn new(index: Option<usize>, command: Command) -> Self {
+        let mut edit = TextEdit {
+            index,
+            original: index.map(|_| command.clone()),
+            command,
+            cursor: 0,
+            anchor: 0,
+            preedit: String::new(),
+            preedit_cursor: 0,
+        };
+        edit.cursor = edit.text().len();
+        edit.anchor = edit.cursor;
+        edit
+    }
+
+    pub fn text(&self) -> &str {
+        self.parts().0
+    }
+
+    fn parts(&self) -> (&str, &[TextRun], &TextStyle) {
+        match &self.command.command_type {
+            CommandType::Text { text, runs, style } | CommandType::Callout { text, runs, style, .. } => {
+                (text, runs, style)
+            }
+            _ => unreachable!("text edits only hold commands with text"),
+        }
+    }
+
+    fn parts_mut(&mut self) -> (&mut String, &mut Vec<TextRun>, &mut TextStyle) {
+        match &mut self.command.command_type {
+            CommandType::Text { text, runs, style } | CommandType::Callout { text, runs, style, .. } => {
+                (text, runs, style)
+            }
+            _ => unreachable!("text edits only hold commands with text"),
+        }
+    }
+
+    /// The style of the whole text, to change it.
+    pub fn style_mut(&mut self) -> &mut TextStyle {
+        self.parts_mut().2
+    }
+
+    /// Turns `emphasis` on or off for the selected text.
+    pub fn toggle_emphasis(&mut self, emphasis: Emphasis) {
+        let selection = self.selection();
+        rich_text::toggle(self.parts_mut().1, selection, emphasis);
+    }
+
+    /// The selected byte range, empty when nothing is selected.
+    pub fn selection(&self) -> std::ops::Range<usize> {
+        self.cursor.min(self.anchor)..self.cursor.max(self.anchor)
+    }
+
+    /// The command as it should be shown, with the input method's
+    /// composition at the caret.
+    pub fn displayed(&self) -> Command {
+        let mut command = self.command.clone();
+        if let CommandType::Text { text, runs, .. } | CommandType::Callout { text, runs, .. } =
+            &mut command.command_type
+        {
+            text.insert_str(self.cursor, &self.preedit);
+            rich_text::replace(runs, self.cursor..self.cursor, self.preedit.len());
+        }
+        command
+    }
+
+    /// Replaces the selection, if any, with `text` and puts the caret after it.
+    pub fn insert(&mut self, text: &str) {
+        let selection = self.selection();
+        let (current, runs, _) = self.parts_mut();
+        current.replace_range(selection.clone(), text);
+        rich_text::replace(runs, selection.clone(), text.len());
+        self.cursor = selection.start + text.len();
+        self.anchor = self.cursor;
+    }
+
+    /// Removes the selection, or the character before the caret.
+    pub fn delete_backward(&mut self) {
+        if self.cursor == self.anchor {
+            self.anchor = self.previous_boundary(self.cursor);
+        }
+        self.insert("");
+    }
+
+    /// Removes the selection, or the character after the caret.
+    pub fn delete_forward(&mut self) {
+        if self.cursor == self.anchor {
+            self.anchor = self.next_boundary(self.cursor);
+        }
+        self.insert("");
+    }
+
+    /// Removes `count` characters starting `offset` characters from the
+    /// caret, for input methods that rewrite what was committed. Returns
+    /// whether they were there to remove.
+    pub fn delete_surrounding(&mut self, offset: i32, count: i32) -> bool {
+        let text = self.text();
+        let cursor = text[..self.cursor].chars().count() as i32;
+        let byte_index = |characters: i32| match characters {
+            characters if characters < 0 => None,
+            characters => text
+                .char_indices()
+                .map(|(index, _)| index)
+                .chain([text.len()])
+                .nth(characters as usize),
+        };
+        let (Some(start), Some(end)) = (byte_index(cursor + offset), byte_index(cursor + offset + count)) else {
+            return false;
+        };
+        self.anchor = start;
+        self.cursor = end;
+        self.insert("");
+        true
+    }
+
+    /// Moves the caret to `index`, keeping the other end of the selection
+    /// where it was when `extend`.
+    pub fn move_to(&mut self, index: usize, extend: bool) {
+        self.cursor = index.min(self.text().len());
+        if !extend {
+            self.anchor = self.cursor;
+        }
+    }
+
+    pub fn select_all(&mut self) {
+        self.anchor = 0;
+        self.cursor = self.text().len();
+    }
+
+    /// Where the character before `index` starts.
+    pub fn previous_boundary(&self, index: usize) -> usize {
+        self.text()[..index]
+            .char_indices()
+            .next_back()
+            .map_or(0, |(start, _)| start)
+    }
+
+    /// Where the character after `index` ends.
+    pub fn next_boundary(&self, index: usize) -> usize {
+        self.text()[index..]
+            .chars()
+            .next()
+            .map_or(index, |character| index + character.len_utf8())
+    }
+
+    /// The caret's index after moving `lines` lines up (negative) or down in
+    /// `layout`, keeping as close as possible to the same horizontal
+    /// position. Moving past the first or last line goes to its end.
+    pub fn line_boundary(&self, layout: &pango::Layout, lines: i32) -> usize {
+        let (line, x) = layout.index_to_line_x(self.cursor as i32, false);
+        let target = line + lines;
+        if target < 0 {
+            return 0;
+        }
+        if target >= layout.line_count() {
+            return self.text().len();
+        }
+        let Some(line) = layout.line_readonly(target) else {
+            return self.cursor;
+        };
+        self.hit_index(line.x_to_index(x))
+    }
+
+    /// The caret's index at the start (`end` false) or end of its line.
+    pub fn line_end(&self, layout: &pango::Layout, end: bool) -> usize {
+        let (line, _) = layout.index_to_line_x(self.cursor as i32, false);
+        let Some(line) = layout.line_readonly(line) else {
+            return self.cursor;
+        };
+        let start = line.start_index() as usize;
+        if !end {
+            return start;
+        }
+        // Stop before the line break rather than after it.
+        let length = line.length() as usize;
+        let line_text = &self.text()[start..start + length];
+        start + line_text.trim_end_matches(['\n', '\r']).len()
+    }
+
+    /// The caret index closest to `point`, relative to where the text
+    /// starts, in `layout`.
+    pub fn index_at(&self, layout: &pango::Layout, point: (f32, f32)) -> usize {
+        let (_, index, trailing) = layout.xy_to_index(
+            (point.0 * pango::SCALE as f32) as i32,
+            (point.1 * pango::SCALE as f32) as i32,
+        );
+        self.trailing_index(index as usize, trailing as usize)
+    }
+
+    fn hit_index(&self, hit: pango::HitPosition) -> usize {
+        self.trailing_index(hit.index() as usize, hit.trailing() as usize)
+    }
+
+    /// Pango reports hits as the start of a grapheme and how many
+    /// characters of it come before the position.
+    fn trailing_index(&self, index: usize, trailing: usize) -> usize {
+        let text = self.text();
+        let index = index.min(text.len());
+        text[index..]
+            .char_indices()
+            .nth(trailing)
+            .map_or(text.len(), |(offset, _)| index + offset)
+    }
+
+    /// The layout of the text as shown, and where the caret is in it.
+    fn displayed_layout(&self, widget: &impl IsA<gtk4::Widget>) -> (pango::Layout, usize) {
+        let layout = match &self.displayed().command_type {
+            CommandType::Text { text, runs, style } | CommandType::Callout { text, runs, style, .. } => {
+                Command::text_layout(&widget.pango_context(), text, runs, style)
+            }
+            _ => unreachable!("text edits only hold commands with text"),
+        };
+        (layout, self.cursor + self.preedit_cursor)
+    }
+
+    /// The layout of the text without the input method's composition, the
+    /// one caret indices refer to.
+    pub fn layout(&self, widget: &impl IsA<gtk4::Widget>) -> pango::Layout {
+        let (text, runs, style) = self.parts();
+        Command::text_layout(&widget.pango_context(), text, runs, style)
+    }
+
+    /// Where the caret is drawn, in widget coordinates.
+    pub fn caret_rect(&self, widget: &impl IsA<gtk4::Widget>) -> graphene::Rect {
+        let (layout, caret) = self.displayed_layout(widget);
+        let position = layout.index_to_pos(caret as i32);
+        let scale = pango::SCALE as f32;
+        graphene::Rect::new(
+            self.command.start.0 + position.x() as f32 / scale,
+            self.command.start.1 + position.y() as f32 / scale,
+            1.5,
+            position.height() as f32 / scale,
+        )
+    }
+
+    /// Whether `point` is on the text, its callout or its caret, where
+    /// clicking moves the caret instead of ending the edit.
+    pub fn contains(&self, point: (f32, f32), widget: &impl IsA<gtk4::Widget>) -> bool {
+        self.displayed().hit_test(point, widget.clone())
+            || self.caret_rect(widget).contains_point(&graphene::Point::new(point.0, point.1))
+    }
+
+    /// Draws the caret and the selected text's background over the text.
+    pub fn draw_to_snapshot(&self, snapshot: &gtk4::Snapshot, widget: impl IsA<gtk4::Widget>) {
+        let selection = self.selection();
+        // Indices past the caret move while composing, which replaces the
+        // selection anyway.
+        if !selection.is_empty() && self.preedit.is_empty() {
+            let layout = self.layout(&widget);
+            let scale = pango::SCALE as f32;
+            let background = gdk4::RGBA::new(ACCENT.red(), ACCENT.green(), ACCENT.blue(), 0.3);
+            for line in (0..layout.line_count()).filter_map(|line| layout.line_readonly(line)) {
+                let position = layout.index_to_pos(line.start_index());
+                let ranges = line.x_ranges(selection.start as i32, selection.end as i32);
+                for range in ranges.chunks_exact(2) {
+                    let rect = graphene::Rect::new(
+                        self.command.start.0 + range[0] as f32 / scale,
+                        self.command.start.1 + position.y() as f32 / scale,
+                        (range[1] - range[0]) as f32 / scale,
+                        position.height() as f32 / scale,
+                    );
+                    snapshot.append_color(&background, &rect);
+                }
+            }
+        }
+        snapshot.append_color(&ACCENT, &self.caret_rect(&widget));
+    }
+
+    /// The command to record once editing ends, `None` when the text was
+    /// emptied.
+    pub fn finished(self) -> Option<Command> {
+        Some(self.command).filter(|command| !command.is_degenerate())
+    }
+}
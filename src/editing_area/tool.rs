@@ -0,0 +1,148 @@
+use super::command::{contrasting, Command, CommandType};
+use super::rich_text::TextStyle;
+
+/// Where a callout's box goes, from what it points at, when it is placed
+/// with a click rather than a drag.
+pub const CALLOUT_CLICK_OFFSET: (f32, f32) = (32.0, -48.0);
+
+/// What dragging on the editing area does.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, glib::Enum)]
+#[enum_type(name = "WTool")]
+pub enum Tool {
+    /// Drag out the region that gets saved.
+    #[default]
+    Selection,
+    Rectangle,
+    Circle,
+    Line,
+    Arrow,
+    Freehand,
+    Text,
+    Blur,
+    Pixelate,
+    Redact,
+    /// Click to place the next numbered step.
+    StepMarker,
+    /// Drag from what to point at to where the text box goes.
+    Callout,
+}
+
+/// How the commands a tool creates look.
+#[derive(Debug, Clone, PartialEq, glib::Boxed)]
+#[boxed_type(name = "ToolStyle")]
+pub struct ToolStyle {
+    pub color: u32,
+    pub width: f32,
+    pub fill_color: Option<u32>,
+    /// How the text and callout tools' text looks.
+    pub text_style: TextStyle,
+    pub blur_radius: f32,
+    pub block_size: f32,
+    pub marker_radius: f32,
+}
+
+impl Default for ToolStyle {
+    fn default() -> Self {
+        ToolStyle {
+            color: 0xFF0000FF, // Red
+            width: 4.0,
+            fill_color: None,
+            text_style: TextStyle::default(),
+            blur_radius: 12.0,
+            block_size: 12.0,
+            marker_radius: 14.0,
+        }
+    }
+}
+
+impl Tool {
+    /// Starts the command this tool draws from a drag beginning at `start`.
+    /// Returns `None` for the selection tool, which draws nothing.
+    pub fn begin_command(self, start: (f32, f32), style: &ToolStyle) -> Option<Command> {
+        let command_type = match self {
+            Tool::Selection => return None,
+            Tool::Rectangle => CommandType::Rectangle { end: start },
+            Tool::Circle => CommandType::Circle { end: start },
+            Tool::Line => CommandType::Line { end: start },
+            Tool::Arrow => CommandType::Arrow { end: start },
+            Tool::Freehand => CommandType::Freehand { points: Vec::new() },
+            // Typed in once placed.
+            Tool::Text => CommandType::Text {
+                text: String::new(),
+                runs: Vec::new(),
+                style: style.text_style.clone(),
+            },
+            Tool::Blur => CommandType::Blur {
+                end: start,
+                radius: style.blur_radius,
+            },
+            Tool::Pixelate => CommandType::Pixelate {
+                end: start,
+                block_size: style.block_size,
+            },
+            Tool::Redact => CommandType::Redact { end: start },
+            Tool::StepMarker => CommandType::StepMarker { radius: style.marker_radius },
+            Tool::Callout => CommandType::Callout {
+                anchor: start,
+                text: String::new(),
+                runs: Vec::new(),
+                style: style.text_style.clone(),
+            },
+        };
+        // Text is filled in the stroke color, callouts always have a box to
+        // write in, everything else uses the fill color, if any.
+        let fill_color = match self {
+            Tool::Text => Some(style.color),
+            Tool::Callout => Some(style.fill_color.unwrap_or(contrasting(style.color))),
+            _ => style.fill_color,
+        };
+        Some(Command {
+            command_type,
+            start,
+            color: style.color,
+            width: style.width,
+            fill_color,
+        })
+    }
+}
+
+impl Command {
+    /// Follows the pointer to `point` while the command is being drawn.
+    pub fn drag_to(&mut self, point: (f32, f32)) {
+        match &mut self.command_type {
+            CommandType::Rectangle { end }
+            | CommandType::Circle { end }
+            | CommandType::Line { end }
+            | CommandType::Arrow { end }
+            | CommandType::Blur { end, .. }
+            | CommandType::Pixelate { end, .. }
+            | CommandType::Redact { end } => *end = point,
+            CommandType::Freehand { points } => points.push(point),
+            // The box follows the pointer, the tail points where the drag
+            // started.
+            CommandType::Callout { .. } => self.start = point,
+            // Text and markers stay where they were clicked.
+            CommandType::Text { .. } | CommandType::StepMarker { .. } => {}
+        }
+    }
+
+    /// Whether the command would draw nothing (or nonsense, for an arrow
+    /// with no direction), which happens when a drag does not move.
+    pub fn is_degenerate(&self) -> bool {
+        match &self.command_type {
+            CommandType::Rectangle { end }
+            | CommandType::Circle { end }
+            | CommandType::Line { end }
+            | CommandType::Arrow { end } => *end == self.start,
+            // Hiding a line is hiding nothing.
+            CommandType::Blur { end, .. }
+            | CommandType::Pixelate { end, .. }
+            | CommandType::Redact { end } => {
+                end.0 == self.start.0 || end.1 == self.start.1
+            }
+            CommandType::Freehand { points } => points.is_empty(),
+            CommandType::Text { text, .. } | CommandType::Callout { text, .. } => text.is_empty(),
+            CommandType::StepMarker { radius } => *radius <= 0.0,
+        }
+    }
+}
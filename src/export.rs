@@ -0,0 +1,411 @@
+//! Encoding rendered images to files.
+
+use std::fmt;
+use std::io::Write;
+use std::path::Path;
+
+use image::ImageEncoder;
+
+use crate::render::RenderError;
+
+/// Quality used for lossy formats when none is given.
+pub const DEFAULT_QUALITY: u8 = 90;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    Png,
+    /// Lossy, without transparency.
+    Jpeg,
+    /// Lossless WebP.
+    WebP,
+    WebPLossy,
+    Qoi,
+}
+
+impl ExportFormat {
+    /// Parses a format name as accepted on the command line.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "png" => Some(ExportFormat::Png),
+            "jpg" | "jpeg" => Some(ExportFormat::Jpeg),
+            "webp" => Some(ExportFormat::WebP),
+            "webp-lossy" => Some(ExportFormat::WebPLossy),
+            "qoi" => Some(ExportFormat::Qoi),
+            _ => None,
+        }
+    }
+
+    /// The extension files in this format are usually given.
+    pub const fn extension(self) -> &'static str {
+        match self {
+            ExportFormat::Png => "png",
+            ExportFormat::Jpeg => "jpg",
+            ExportFormat::WebP | ExportFormat::WebPLossy => "webp",
+            ExportFormat::Qoi => "qoi",
+        }
+    }
+
+    /// Guesses the format from a file extension. WebP files are lossless
+    /// unless asked otherwise.
+    pub fn from_extension(path: &Path) -> Option<Self> {
+        let extension = path.extension()?.to_str()?;
+        match Self::from_name(extension)? {
+            ExportFormat::WebPLossy => None,
+            format => Some(format),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExportOptions {
+    /// Overrides the format guessed from the file extension.
+    pub format: Option<ExportFormat>,
+    /// From 1 to 100, used by JPEG and lossy WebP.
+    pub quality: u8,
+}
+
+impl Default for ExportOptions {
+    fn default() -> Self {
+        ExportOptions {
+            format: None,
+            quality: DEFAULT_QUALITY,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ExportError {
+    /// Neither the options nor the file extension say what to encode to.
+    UnknownFormat,
+    Render(RenderError),
+    Io(std::io::Error),
+    Encode(String),
+    Clipboard(String),
+}
+
+impl fmt::Display for ExportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExportError::UnknownFormat => {
+                write!(f, "could not tell the image format from the file name")
+            }
+            ExportError::Render(error) => write!(f, "could not render the image: {}", error),
+            ExportError::Io(error) => write!(f, "could not write the image: {}", error),
+            ExportError::Encode(reason) => write!(f, "could not encode the image: {}", reason),
+            ExportError::Clipboard(reason) => {
+                write!(f, "could not put the image on the clipboard: {}", reason)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ExportError {}
+
+impl From<RenderError> for ExportError {
+    fn from(error: RenderError) -> Self {
+        ExportError::Render(error)
+    }
+}
+
+impl From<std::io::Error> for ExportError {
+    fn from(error: std::io::Error) -> Self {
+        ExportError::Io(error)
+    }
+}
+
+impl From<image::ImageError> for ExportError {
+    fn from(error: image::ImageError) -> Self {
+        ExportError::Encode(error.to_string())
+    }
+}
+
+impl From<cairo::BorrowError> for ExportError {
+    fn from(error: cairo::BorrowError) -> Self {
+        ExportError::Encode(error.to_string())
+    }
+}
+
+/// 8-bit RGBA pixels with straight (not premultiplied) alpha, what every
+/// encoder wants.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pixels {
+    pub width: u32,
+    pub height: u32,
+    pub data: Vec<u8>,
+}
+
+impl Pixels {
+    /// Converts a cairo ARGB32 surface, which is premultiplied and in
+    /// native endianness.
+    pub fn from_surface(surface: &cairo::ImageSurface) -> Result<Self, ExportError> {
+        let width = surface.width() as u32;
+        let height = surface.height() as u32;
+        let stride = surface.stride() as usize;
+        let mut data = Vec::with_capacity(width as usize * height as usize * 4);
+
+        surface.with_data(|surface_data| {
+            for row in surface_data.chunks(stride).take(height as usize) {
+                for pixel in row[..width as usize * 4].chunks_exact(4) {
+                    let pixel = u32::from_ne_bytes([pixel[0], pixel[1], pixel[2], pixel[3]]);
+                    let alpha = pixel >> 24;
+                    let unpremultiply = |channel: u32| {
+                        if alpha == 0 {
+                            0
+                        } else {
+                            ((channel * 255 + alpha / 2) / alpha).min(255) as u8
+                        }
+                    };
+                    data.push(unpremultiply((pixel >> 16) & 0xFF));
+                    data.push(unpremultiply((pixel >> 8) & 0xFF));
+                    data.push(unpremultiply(pixel & 0xFF));
+                    data.push(alpha as u8);
+                }
+            }
+        })?;
+
+        Ok(Pixels { width, height, data })
+    }
+
+    /// Drops the alpha channel, blending onto white so transparent areas
+    /// do not turn black.
+    fn to_rgb(&self) -> Vec<u8> {
+        self.data
+            .chunks_exact(4)
+            .flat_map(|pixel| {
+                let alpha = pixel[3] as u32;
+                let blend = |channel: u8| ((channel as u32 * alpha + 255 * (255 - alpha) + 127) / 255) as u8;
+                [blend(pixel[0]), blend(pixel[1]), blend(pixel[2])]
+            })
+            .collect()
+    }
+}
+
+/// Encodes the pixels in the given format.
+pub fn encode(pixels: &Pixels, format: ExportFormat, quality: u8) -> Result<Vec<u8>, ExportError> {
+    let quality = quality.clamp(1, 100);
+    let mut encoded = Vec::new();
+    match format {
+        ExportFormat::Png => {
+            image::codecs::png::PngEncoder::new(&mut encoded).write_image(
+                &pixels.data,
+                pixels.width,
+                pixels.height,
+                image::ExtendedColorType::Rgba8,
+            )?;
+        }
+        ExportFormat::Jpeg => {
+            image::codecs::jpeg::JpegEncoder::new_with_quality(&mut encoded, quality).write_image(
+                &pixels.to_rgb(),
+                pixels.width,
+                pixels.height,
+                image::ExtendedColorType::Rgb8,
+            )?;
+        }
+        ExportFormat::WebP | ExportFormat::WebPLossy => {
+            let lossless = format == ExportFormat::WebP;
+            let memory = webp::Encoder::from_rgba(&pixels.data, pixels.width, pixels.height)
+                .encode_simple(lossless, quality as f32)
+                .map_err(|error| ExportError::Encode(format!("{:?}", error)))?;
+            encoded.extend_from_slice(&memory);
+        }
+        ExportFormat::Qoi => {
+            image::codecs::qoi::QoiEncoder::new(&mut encoded).write_image(
+                &pixels.data,
+                pixels.width,
+                pixels.height,
+                image::ExtendedColorType::Rgba8,
+            )?;
+        }
+    }
+    Ok(encoded)
+}
+
+/// Encodes the surface and writes it to `path`.
+pub fn export_to_file(
+    surface: &cairo::ImageSurface,
+    path: &Path,
+    options: &ExportOptions,
+) -> Result<(), ExportError> {
+    let format = options
+        .format
+        .or_else(|| ExportFormat::from_extension(path))
+        .ok_or(ExportError::UnknownFormat)?;
+    let pixels = Pixels::from_surface(surface)?;
+    let encoded = encode(&pixels, format, options.quality)?;
+
+    let mut file = std::fs::File::create(path)?;
+    file.write_all(&encoded)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use gdk4::prelude::*;
+
+    use super::*;
+    use crate::capture::{CaptureBoxed, CapturedOutput};
+    use crate::editing_area::{Change, Command, CommandsBoxed, Selection};
+    use crate::render;
+
+    const SIZE: u32 = 64;
+    /// The redacted square as (left, top, right, bottom), on JPEG and WebP
+    /// block boundaries so their blocks are either all black or untouched.
+    const BOX: (u32, u32, u32, u32) = (16, 16, 48, 48);
+    const BACKGROUND: [u8; 3] = [0x80, 0x80, 0x80];
+    const SECRETS: [[u8; 3]; 3] = [[0x13, 0x57, 0x9B], [0xC0, 0xFF, 0xEE], [0xBA, 0xDB, 0x01]];
+    /// How far lossy formats may drift from the exact colors.
+    const LOSSY_TOLERANCE: u8 = 24;
+
+    fn in_box(x: u32, y: u32) -> bool {
+        (BOX.0..BOX.2).contains(&x) && (BOX.1..BOX.3).contains(&y)
+    }
+
+    /// A single output, gray with a checkerboard of secret colors in the box.
+    fn capture() -> CaptureBoxed {
+        let mut data = Vec::new();
+        for y in 0..SIZE {
+            for x in 0..SIZE {
+                let secret = SECRETS[((x + y) % SECRETS.len() as u32) as usize];
+                data.extend_from_slice(if in_box(x, y) { &secret } else { &BACKGROUND });
+            }
+        }
+        let texture = gdk4::MemoryTexture::new(
+            SIZE as i32,
+            SIZE as i32,
+            gdk4::MemoryFormat::R8g8b8,
+            &glib::Bytes::from_owned(data),
+            SIZE as usize * 3,
+        );
+        vec![CapturedOutput {
+            name: None,
+            x: 0,
+            y: 0,
+            width: SIZE as i32,
+            height: SIZE as i32,
+            texture: texture.upcast(),
+        }]
+        .into()
+    }
+
+    /// Decodes `encoded` to RGBA pixels.
+    fn decode(encoded: &[u8], format: ExportFormat) -> (u32, u32, Vec<[u8; 4]>) {
+        if let ExportFormat::WebP | ExportFormat::WebPLossy = format {
+            let image = webp::Decoder::new(encoded).decode().expect("WebP decodes");
+            let channels = if image.is_alpha() { 4 } else { 3 };
+            let pixels = image
+                .chunks_exact(channels)
+                .map(|pixel| [pixel[0], pixel[1], pixel[2], if channels == 4 { pixel[3] } else { 255 }])
+                .collect();
+            return (image.width(), image.height(), pixels);
+        }
+        let image = image::load_from_memory(encoded).expect("image decodes").to_rgba8();
+        let pixels = image.pixels().map(|pixel| pixel.0).collect();
+        (image.width(), image.height(), pixels)
+    }
+
+    /// Fails if the container carries anything but the image: text, EXIF,
+    /// XMP, color profiles or thumbnails.
+    fn assert_no_metadata(encoded: &[u8], format: ExportFormat) {
+        match format {
+            ExportFormat::Png => {
+                let mut offset = 8;
+                while offset + 8 <= encoded.len() {
+                    let length = u32::from_be_bytes(encoded[offset..offset + 4].try_into().unwrap()) as usize;
+                    let kind = &encoded[offset + 4..offset + 8];
+                    assert!(
+                        [b"IHDR", b"IDAT", b"IEND"].iter().any(|allowed| kind == *allowed),
+                        "unexpected PNG chunk {:?}",
+                        String::from_utf8_lossy(kind)
+                    );
+                    offset += 12 + length;
+                }
+            }
+            ExportFormat::Jpeg => {
+                let mut offset = 2;
+                // Only entropy coded data follows the start of scan.
+                while offset + 4 <= encoded.len() && encoded[offset + 1] != 0xDA {
+                    let marker = encoded[offset + 1];
+                    // APP1 and up carry EXIF, XMP and thumbnails, COM comments.
+                    assert!(!(0xE1..=0xEF).contains(&marker) && marker != 0xFE, "JPEG has segment {:#04X}", marker);
+                    assert!(
+                        !(marker == 0xE0 && encoded[offset + 4..].starts_with(b"JFXX\0")),
+                        "JPEG has a JFXX thumbnail"
+                    );
+                    let length = u16::from_be_bytes([encoded[offset + 2], encoded[offset + 3]]) as usize;
+                    offset += 2 + length;
+                }
+            }
+            ExportFormat::WebP | ExportFormat::WebPLossy => {
+                assert!(encoded.starts_with(b"RIFF") && encoded[8..12] == *b"WEBP", "not a WebP file");
+                let mut offset = 12;
+                while offset + 8 <= encoded.len() {
+                    let kind = &encoded[offset..offset + 4];
+                    let length = u32::from_le_bytes(encoded[offset + 4..offset + 8].try_into().unwrap()) as usize;
+                    assert!(
+                        [b"VP8 ", b"VP8L", b"VP8X", b"ALPH"].iter().any(|allowed| kind == *allowed),
+                        "unexpected WebP chunk {:?}",
+                        String::from_utf8_lossy(kind)
+                    );
+                    offset += 8 + length + (length & 1);
+                }
+            }
+            // QOI has nowhere to put metadata, but nothing may trail the end marker.
+            ExportFormat::Qoi => assert!(encoded.ends_with(&[0, 0, 0, 0, 0, 0, 0, 1]), "QOI has trailing data"),
+        }
+    }
+
+    #[test]
+    fn redaction_leaves_nothing_of_the_secret() {
+        let selection = Selection::new_from_size((0.0, 0.0), SIZE as f32, SIZE as f32);
+        let redacted = Selection::new_from_size(
+            (BOX.0 as f32, BOX.1 as f32),
+            (BOX.2 - BOX.0) as f32,
+            (BOX.3 - BOX.1) as f32,
+        );
+        let commands = CommandsBoxed::from(vec![Change::Add(Command::redact(&redacted))]);
+        let surface = render::render_selection(&capture(), (0.0, 0.0), &commands, &selection, 1.0).unwrap();
+        let pixels = Pixels::from_surface(&surface).unwrap();
+
+        for format in [
+            ExportFormat::Png,
+            ExportFormat::Jpeg,
+            ExportFormat::WebP,
+            ExportFormat::WebPLossy,
+            ExportFormat::Qoi,
+        ] {
+            let encoded = encode(&pixels, format, DEFAULT_QUALITY).unwrap();
+            assert_no_metadata(&encoded, format);
+
+            let tolerance = match format {
+                ExportFormat::Jpeg | ExportFormat::WebPLossy => LOSSY_TOLERANCE,
+                _ => 0,
+            };
+            let close = |a: u8, b: u8| a.abs_diff(b) <= tolerance;
+            let (width, height, decoded) = decode(&encoded, format);
+            assert_eq!((width, height), (SIZE, SIZE), "{:?}", format);
+            for (index, pixel) in decoded.iter().enumerate() {
+                let (x, y) = (index as u32 % SIZE, index as u32 / SIZE);
+                for secret in SECRETS {
+                    assert!(
+                        !pixel.iter().zip(secret).all(|(channel, secret)| close(*channel, secret)),
+                        "{:?}: secret color {:?} left at {},{}",
+                        format,
+                        pixel,
+                        x,
+                        y
+                    );
+                }
+                if in_box(x, y) {
+                    assert!(
+                        pixel[..3].iter().all(|channel| close(*channel, 0)),
+                        "{:?}: pixel {:?} at {},{} in the box is not black",
+                        format,
+                        pixel,
+                        x,
+                        y
+                    );
+                }
+            }
+        }
+    }
+}
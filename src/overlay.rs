@@ -0,0 +1,62 @@
+use gtk4::prelude::*;
+use gtk4::{Application, ApplicationWindow};
+use gtk4_layer_shell::{Edge, KeyboardMode, Layer, LayerShell};
+
+use crate::editing_area::EditingArea;
+
+/// Setting this environment variable to anything forces the fullscreen
+/// fallback, even if the compositor supports wlr-layer-shell.
+pub const NO_LAYER_SHELL_ENV: &str = "WINDSHOT_NO_LAYER_SHELL";
+
+/// The way the overlay window ended up being presented.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OverlayMode {
+    /// A layer surface on the overlay layer, covering the entire output.
+    LayerShell,
+    /// A regular undecorated toplevel, asked to go fullscreen.
+    Fullscreen,
+}
+
+/// Returns whether the overlay will be placed on a layer surface.
+pub fn layer_shell_available() -> bool {
+    std::env::var_os(NO_LAYER_SHELL_ENV).is_none() && gtk4_layer_shell::is_supported()
+}
+
+/// Creates the window the [`EditingArea`] is presented in.
+///
+/// When the compositor supports wlr-layer-shell the window is placed on
+/// the overlay layer, anchored to every edge of `monitor`, ignoring other
+/// surfaces' exclusive zones and grabbing the keyboard exclusively, the
+/// same way slurp does. Otherwise it falls back to a fullscreen toplevel.
+pub fn build_overlay_window(
+    app: &Application,
+    monitor: Option<&gdk4::Monitor>,
+    editing_area: &EditingArea,
+) -> (ApplicationWindow, OverlayMode) {
+    let window = ApplicationWindow::new(app);
+    window.set_title(Some("Windshot"));
+    window.set_decorated(false);
+
+    let mode = if layer_shell_available() {
+        window.init_layer_shell();
+        window.set_namespace(Some("windshot"));
+        window.set_layer(Layer::Overlay);
+        for edge in [Edge::Top, Edge::Bottom, Edge::Left, Edge::Right] {
+            window.set_anchor(edge, true);
+        }
+        // -1 means we do not get moved around by panels and bars.
+        window.set_exclusive_zone(-1);
+        window.set_keyboard_mode(KeyboardMode::Exclusive);
+        window.set_monitor(monitor);
+        OverlayMode::LayerShell
+    } else {
+        match monitor {
+            Some(monitor) => window.fullscreen_on_monitor(monitor),
+            None => window.fullscreen(),
+        }
+        OverlayMode::Fullscreen
+    };
+
+    window.set_child(Some(editing_area));
+    (window, mode)
+}
@@ -0,0 +1,121 @@
+//! Offscreen rendering of the final image with cairo, without needing a
+//! display. This is what ends up in files and on the clipboard.
+
+use std::fmt;
+
+use gdk4::prelude::*;
+
+use crate::capture::{CaptureBoxed, CapturedOutput};
+use crate::editing_area::{number_steps, CommandsBoxed, Selection};
+
+#[derive(Debug)]
+pub enum RenderError {
+    /// The selection has no area, so there is nothing to render.
+    EmptySelection,
+    Cairo(cairo::Error),
+}
+
+impl fmt::Display for RenderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RenderError::EmptySelection => write!(f, "the selection is empty"),
+            RenderError::Cairo(error) => write!(f, "cairo error: {}", error),
+        }
+    }
+}
+
+impl std::error::Error for RenderError {}
+
+impl From<cairo::Error> for RenderError {
+    fn from(error: cairo::Error) -> Self {
+        RenderError::Cairo(error)
+    }
+}
+
+/// Renders what the editing area shows inside `selection`, minus the
+/// selection overlay itself.
+///
+/// `origin` is the logical position of the editing area in the
+/// compositor's global space, like [`crate::editing_area::EditingArea`]'s
+/// `origin-x` and `origin-y`. `scale` is the number of image pixels per
+/// logical pixel. Each output is resampled from its own scale to it, so
+/// a selection spanning outputs of mixed scales comes out in one piece.
+pub fn render_selection(
+    capture: &CaptureBoxed,
+    origin: (f32, f32),
+    commands: &CommandsBoxed,
+    selection: &Selection,
+    scale: f64,
+) -> Result<cairo::ImageSurface, RenderError> {
+    // Same rounding as the rectangle the editing area leaves undimmed, when
+    // `scale` is the one it is shown at.
+    let (x, y, width, height) = selection.to_buffer_rect((0.0, 0.0), scale);
+    if width <= 0 || height <= 0 {
+        return Err(RenderError::EmptySelection);
+    }
+
+    let surface = cairo::ImageSurface::create(cairo::Format::ARgb32, width, height)?;
+    let cr = cairo::Context::new(&surface)?;
+    cr.translate(-x as f64, -y as f64);
+    cr.scale(scale, scale);
+
+    for output in capture.iter() {
+        draw_output(&cr, output, origin)?;
+    }
+    let commands = commands.resolve();
+    // Before anything else, and again in order below, which is harmless.
+    for (_, command) in commands.iter().filter(|(_, command)| command.is_redact_box()) {
+        command.draw_to_cairo(&cr, None)?;
+    }
+    for (command, step) in number_steps(commands.iter().map(|(_, command)| *command)) {
+        command.draw_to_cairo(&cr, step)?;
+    }
+
+    drop(cr);
+    surface.flush();
+    Ok(surface)
+}
+
+/// Draws a captured output where [`CapturedOutput::draw_to_snapshot`] would.
+fn draw_output(
+    cr: &cairo::Context,
+    output: &CapturedOutput,
+    origin: (f32, f32),
+) -> Result<(), RenderError> {
+    let image = texture_to_surface(&output.texture)?;
+    let texture_width = output.texture.width() as f64;
+    let texture_height = output.texture.height() as f64;
+
+    cr.save()?;
+    cr.translate(
+        (output.x as f32 - origin.0) as f64,
+        (output.y as f32 - origin.1) as f64,
+    );
+    cr.scale(
+        output.width as f64 / texture_width,
+        output.height as f64 / texture_height,
+    );
+    cr.set_source_surface(&image, 0.0, 0.0)?;
+    cr.source().set_filter(cairo::Filter::Good);
+    cr.rectangle(0.0, 0.0, texture_width, texture_height);
+    cr.fill()?;
+    cr.restore()?;
+    Ok(())
+}
+
+/// Copies a texture's pixels into a cairo image surface.
+pub fn texture_to_surface(texture: &gdk4::Texture) -> Result<cairo::ImageSurface, RenderError> {
+    let width = texture.width();
+    let height = texture.height();
+    // gdk_texture_download produces cairo's native ARGB32 layout.
+    let stride = cairo::Format::ARgb32.stride_for_width(width as u32)?;
+    let mut data = vec![0u8; stride as usize * height as usize];
+    texture.download(&mut data, stride as usize);
+    Ok(cairo::ImageSurface::create_for_data(
+        data,
+        cairo::Format::ARgb32,
+        width,
+        height,
+        stride,
+    )?)
+}